@@ -1,5 +1,13 @@
 use once_cell::sync::Lazy;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventSubTransport {
+    Webhook,
+    WebSocket,
+    /// No EventSub at all, streams are polled from Helix.
+    Polling,
+}
+
 pub struct Config {
     // Telegram
     pub telegram_bot_token: String,
@@ -9,21 +17,57 @@ pub struct Config {
 
     pub telegram_mini_app_port: u16,
 
+    /// Users allowed to use /stats, /broadcast and /whois
+    pub admin_user_ids: Vec<u64>,
+
     // Twitch
     pub twitch_client_id: String,
     pub twitch_client_secret: String,
 
+    pub twitch_eventsub_transport: EventSubTransport,
+
     pub twitch_signing_secret: String,
 
     pub twitch_webhook_url: String,
+    /// Other transports only serve `/twitch/health` on it, if it is set
     pub twitch_webhook_port: u16,
 
+    pub twitch_eventsub_websocket_url: String,
+    pub twitch_user_access_token: Option<String>,
+    pub twitch_user_refresh_token: Option<String>,
+
+    /// In seconds, at least 10
+    pub twitch_polling_interval: u64,
+
+    // Notifications
+    pub edit_live_message_on_offline: bool,
+
     // Common
     pub mongodb_connection_string: String,
 }
 
+/// Webhook settings are only required when events are delivered by webhook.
+fn webhook_var(transport: EventSubTransport, name: &str) -> String {
+    match transport {
+        EventSubTransport::Webhook => {
+            std::env::var(name).unwrap_or_else(|_| panic!("{} is not set", name))
+        }
+        EventSubTransport::WebSocket | EventSubTransport::Polling => {
+            std::env::var(name).unwrap_or_default()
+        }
+    }
+}
+
 impl Config {
     fn load() -> Self {
+        let twitch_eventsub_transport = match std::env::var("TWITCH_EVENTSUB_TRANSPORT").as_deref()
+        {
+            Ok("webhook") | Err(_) => EventSubTransport::Webhook,
+            Ok("websocket") => EventSubTransport::WebSocket,
+            Ok("polling") => EventSubTransport::Polling,
+            Ok(_) => panic!("TWITCH_EVENTSUB_TRANSPORT is not one of webhook, websocket, polling"),
+        };
+
         Self {
             telegram_bot_token: std::env::var("TELEGRAM_BOT_TOKEN")
                 .expect("TELEGRAM_BOT_TOKEN is not set"),
@@ -39,20 +83,60 @@ impl Config {
                 .parse()
                 .expect("TELEGRAM_MINI_APP_PORT is not a valid u16"),
 
+            admin_user_ids: std::env::var("ADMIN_USER_IDS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|id| id.trim())
+                        .filter(|id| !id.is_empty())
+                        .map(|id| id.parse().expect("ADMIN_USER_IDS is not a list of u64"))
+                        .collect()
+                })
+                .unwrap_or_default(),
+
             twitch_client_id: std::env::var("TWITCH_CLIENT_ID")
                 .expect("TWITCH_CLIENT_ID is not set"),
             twitch_client_secret: std::env::var("TWITCH_CLIENT_SECRET")
                 .expect("TWITCH_CLIENT_SECRET is not set"),
 
-            twitch_signing_secret: std::env::var("TWITCH_SIGNING_SECRET")
-                .expect("TWITCH_SIGNING_SECRET is not set"),
+            twitch_eventsub_transport,
 
-            twitch_webhook_url: std::env::var("TWITCH_WEBHOOK_URL")
-                .expect("TWITCH_WEBHOOK_URL is not set"),
-            twitch_webhook_port: std::env::var("TWITCH_WEBHOOK_PORT")
-                .expect("TWITCH_WEBHOOK_PORT is not set")
-                .parse()
-                .expect("TWITCH_WEBHOOK_PORT is not a valid u16"),
+            twitch_signing_secret: webhook_var(twitch_eventsub_transport, "TWITCH_SIGNING_SECRET"),
+
+            twitch_webhook_url: webhook_var(twitch_eventsub_transport, "TWITCH_WEBHOOK_URL"),
+            twitch_webhook_port: match webhook_var(twitch_eventsub_transport, "TWITCH_WEBHOOK_PORT")
+                .as_str()
+            {
+                "" => 0,
+                v => v.parse().expect("TWITCH_WEBHOOK_PORT is not a valid u16"),
+            },
+
+            twitch_eventsub_websocket_url: std::env::var("TWITCH_EVENTSUB_WEBSOCKET_URL")
+                .unwrap_or_else(|_| "wss://eventsub.wss.twitch.tv/ws".to_string()),
+            twitch_user_access_token: std::env::var("TWITCH_USER_ACCESS_TOKEN").ok(),
+            twitch_user_refresh_token: std::env::var("TWITCH_USER_REFRESH_TOKEN").ok(),
+
+            twitch_polling_interval: std::env::var("TWITCH_POLLING_INTERVAL")
+                .map(|v| {
+                    let interval = v
+                        .parse::<u64>()
+                        .expect("TWITCH_POLLING_INTERVAL is not a valid u64");
+
+                    // Faster polling only burns through the Helix rate limit
+                    assert!(
+                        interval >= 10,
+                        "TWITCH_POLLING_INTERVAL must be at least 10 seconds"
+                    );
+
+                    interval
+                })
+                .unwrap_or(60),
+
+            edit_live_message_on_offline: std::env::var("EDIT_LIVE_MESSAGE_ON_OFFLINE")
+                .map(|v| {
+                    v.parse()
+                        .expect("EDIT_LIVE_MESSAGE_ON_OFFLINE is not a valid bool")
+                })
+                .unwrap_or(false),
 
             mongodb_connection_string: std::env::var("MONGODB_CONNECTION_STRING")
                 .expect("MONGODB_CONNECTION_STRING is not set"),
@@ -0,0 +1,504 @@
+/// Languages the bot speaks. Everything else falls back to English.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Language {
+    #[default]
+    En,
+    Ru,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::En, Language::Ru];
+
+    /// Parses a Telegram `language_code` such as `ru` or `en-US`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.split(['-', '_']).next()?.to_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "ru" => Some(Self::Ru),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Ru => "ru",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::En => "English",
+            Self::Ru => "Русский",
+        }
+    }
+}
+
+/// Every text the bot sends. Notification texts are HTML, values passed into
+/// them must already be escaped.
+pub enum Text<'a> {
+    Help,
+    NotAdmin,
+    NotSubscribed,
+
+    SubscribeUsage,
+    SubscribeConfirm(&'a str),
+    SubscribedTo(&'a str),
+    StreamerNotFound,
+    LoginNotFound(&'a str),
+    LoginNotFoundSuggestions(&'a str),
+    Unsubscribed,
+    UnsubscribedFrom(&'a str),
+    UpdatesEnabled,
+    UpdatesDisabled,
+    FavoriteEnabled,
+    FavoriteDisabled,
+
+    TemplateUsage(&'a str),
+    TemplateReset,
+    TemplateSaved(&'a str),
+    TemplateInvalid(&'a str),
+    TemplateRejected(&'a str),
+    ExampleTitle,
+
+    NoSubscriptions,
+    ListHeader {
+        page: usize,
+        pages: usize,
+    },
+    NobodyLive,
+
+    LanguageUsage,
+    LanguageSet,
+
+    QuietUsage,
+    QuietSilent {
+        hours: &'a str,
+        timezone: &'a str,
+    },
+    QuietDigest {
+        hours: &'a str,
+        timezone: &'a str,
+    },
+    QuietOff,
+    TimezoneUsage(&'a str),
+    TimezoneSet(&'a str),
+    TimezoneInvalid(&'a str),
+
+    Stats {
+        chats: usize,
+        subscriptions: u64,
+        streamers: usize,
+        registrations: u64,
+        notifications_today: i64,
+        removed_chats: i64,
+    },
+    BroadcastUsage,
+    BroadcastProgress {
+        sent: usize,
+        failed: usize,
+        total: usize,
+    },
+    BroadcastDone {
+        sent: usize,
+        failed: usize,
+        total: usize,
+    },
+    WhoisUsage,
+    WhoisHeader {
+        chat_id: i64,
+        subscriptions: usize,
+    },
+    WhoisInactive,
+
+    IsLive(&'a str),
+    IsOffline(&'a str),
+    Offline,
+    Viewers(usize),
+    EndedStream(&'a str),
+    EndedStreamLasted(&'a str, &'a str),
+    UpdatedStream(&'a str),
+    Title,
+    Category,
+    Raided {
+        from: &'a str,
+        to: &'a str,
+        viewers: i64,
+    },
+    ChannelRemoved(&'a str),
+    Digest,
+    DigestEnded,
+    WatchOnTwitch,
+    Watch(&'a str),
+    SubscribeTo(&'a str),
+    Subscribe,
+    Duration {
+        hours: i64,
+        minutes: i64,
+    },
+
+    CommandStart,
+    CommandHelp,
+    CommandSubscribe,
+    CommandUnsubscribe,
+    CommandUpdates,
+    CommandList,
+    CommandLive,
+    CommandTemplate,
+    CommandPreview,
+    CommandLanguage,
+    CommandFavorite,
+    CommandQuiet,
+    CommandTimezone,
+    CommandStats,
+    CommandBroadcast,
+    CommandWhois,
+}
+
+impl Text<'_> {
+    pub fn localize(&self, language: Language) -> String {
+        match language {
+            Language::En => self.en(),
+            Language::Ru => self.ru(),
+        }
+    }
+
+    fn en(&self) -> String {
+        match self {
+            Self::Help => "Welcome!\n\n\
+                This bot allow you to subscribe to receive start stream notifications."
+                .to_string(),
+            Self::NotAdmin => "Only chat administrators can change subscriptions here.".to_string(),
+            Self::NotSubscribed => "You are not subscribed to this streamer!".to_string(),
+
+            Self::SubscribeUsage => {
+                "Usage: /subscribe <login or twitch.tv link> [more logins...]".to_string()
+            }
+            Self::SubscribedTo(names) => format!("Subscribed to {}!", names),
+            Self::SubscribeConfirm(name) => {
+                format!("Get notified here when <b>{}</b> goes live?", name)
+            }
+            Self::StreamerNotFound => "Streamer not found!".to_string(),
+            Self::LoginNotFound(login) => format!("Streamer {} not found!", login),
+            Self::LoginNotFoundSuggestions(login) => {
+                format!("Streamer {} not found! Did you mean one of these?", login)
+            }
+            Self::Unsubscribed => "Unsubscribed!".to_string(),
+            Self::UnsubscribedFrom(name) => format!("Unsubscribed from {}!", name),
+            Self::UpdatesEnabled => "Title and category updates enabled!".to_string(),
+            Self::UpdatesDisabled => "Title and category updates disabled!".to_string(),
+            Self::FavoriteEnabled => {
+                "Added to favorites, its streams are announced even during quiet hours!".to_string()
+            }
+            Self::FavoriteDisabled => "Removed from favorites!".to_string(),
+
+            Self::TemplateUsage(placeholders) => format!(
+                "Usage: /template <login> <text>, or /template <login> to go back to the default.\n\n\
+                 The text may use HTML and these placeholders: {}",
+                placeholders
+            ),
+            Self::TemplateReset => "Template reset to the default!".to_string(),
+            Self::TemplateSaved(login) => format!(
+                "Template saved! Use /preview {} to see how it looks.",
+                login
+            ),
+            Self::TemplateInvalid(err) => format!("Invalid template: {}", err),
+            Self::TemplateRejected(err) => format!("Telegram rejected the template: {}", err),
+            Self::ExampleTitle => "Example stream title".to_string(),
+
+            Self::NoSubscriptions => {
+                "You have no subscriptions yet. Use /subscribe to add one!".to_string()
+            }
+            Self::ListHeader { page, pages } => {
+                format!("Your subscriptions (page {} of {}):", page, pages)
+            }
+            Self::NobodyLive => "None of your streamers are live right now.".to_string(),
+
+            Self::LanguageUsage => "Choose the language of the bot:".to_string(),
+            Self::LanguageSet => "The bot speaks English now!".to_string(),
+
+            Self::QuietUsage => {
+                "Usage: /quiet <from>-<to> [silent|digest], e.g. /quiet 23:00-08:00, \
+                 or /quiet off.\n\n\
+                 During quiet hours live notifications come without a sound, or with digest \
+                 are held back and sent as one message when quiet hours end. \
+                 Favorites (/favorite) are announced as usual."
+                    .to_string()
+            }
+            Self::QuietSilent { hours, timezone } => format!(
+                "Quiet hours set to {} ({}), notifications will come without a sound.",
+                hours, timezone
+            ),
+            Self::QuietDigest { hours, timezone } => format!(
+                "Quiet hours set to {} ({}), notifications will be sent as a digest afterwards.",
+                hours, timezone
+            ),
+            Self::QuietOff => "Quiet hours turned off!".to_string(),
+            Self::TimezoneUsage(timezone) => format!(
+                "Usage: /timezone <name>, e.g. /timezone Europe/Moscow.\n\nThe current timezone is {}.",
+                timezone
+            ),
+            Self::TimezoneSet(timezone) => format!("Timezone set to {}!", timezone),
+            Self::TimezoneInvalid(timezone) => format!("Unknown timezone {}!", timezone),
+
+            Self::Stats {
+                chats,
+                subscriptions,
+                streamers,
+                registrations,
+                notifications_today,
+                removed_chats,
+            } => format!(
+                "Chats: {}\n\
+                 Subscriptions: {}\n\
+                 Streamers: {}\n\
+                 Active EventSub registrations: {}\n\
+                 Notifications sent today: {}\n\
+                 Chats removed after blocking the bot: {}",
+                chats, subscriptions, streamers, registrations, notifications_today, removed_chats
+            ),
+            Self::BroadcastUsage => "Usage: /broadcast <text>".to_string(),
+            Self::BroadcastProgress {
+                sent,
+                failed,
+                total,
+            } => format!(
+                "Broadcasting... {} of {} sent, {} failed",
+                sent, total, failed
+            ),
+            Self::BroadcastDone {
+                sent,
+                failed,
+                total,
+            } => format!(
+                "Broadcast finished: {} of {} sent, {} failed",
+                sent, total, failed
+            ),
+            Self::WhoisUsage => "Usage: /whois <user or chat id>".to_string(),
+            Self::WhoisHeader {
+                chat_id,
+                subscriptions,
+            } => format!("Chat {} has {} subscriptions", chat_id, subscriptions),
+            Self::WhoisInactive => "The chat blocked the bot or removed it".to_string(),
+
+            Self::IsLive(name) => format!("🔴 <b>{}</b> is now live!", name),
+            Self::IsOffline(name) => format!("⚫️ <b>{}</b> is offline", name),
+            Self::Offline => "Offline".to_string(),
+            Self::Viewers(viewers) => format!("👀 {} viewers", viewers),
+            Self::EndedStream(name) => format!("⚫️ <b>{}</b> ended the stream", name),
+            Self::EndedStreamLasted(name, lasted) => {
+                format!("⚫️ <b>{}</b> ended the stream (lasted {})", name, lasted)
+            }
+            Self::UpdatedStream(name) => format!("✏️ <b>{}</b> updated the stream", name),
+            Self::Title => "Title".to_string(),
+            Self::Category => "Category".to_string(),
+            Self::Raided { from, to, viewers } => format!(
+                "🚀 <b>{}</b> raided <b>{}</b> with {} viewers",
+                from, to, viewers
+            ),
+            Self::ChannelRemoved(name) => format!(
+                "⚠️ <b>{}</b> is no longer available on Twitch, the subscription is paused",
+                name
+            ),
+            Self::Digest => "🌙 Went live during quiet hours:".to_string(),
+            Self::DigestEnded => "already ended".to_string(),
+            Self::WatchOnTwitch => "Watch on Twitch".to_string(),
+            Self::Watch(name) => format!("Watch {}", name),
+            Self::SubscribeTo(name) => format!("Subscribe to {}", name),
+            Self::Subscribe => "🔔 Subscribe".to_string(),
+            Self::Duration { hours: 0, minutes } => format!("{}m", minutes),
+            Self::Duration { hours, minutes } => format!("{}h {}m", hours, minutes),
+
+            Self::CommandStart => "Start the bot".to_string(),
+            Self::CommandHelp => "Show help".to_string(),
+            Self::CommandSubscribe => {
+                "Subscribe to streamers by login or twitch.tv link".to_string()
+            }
+            Self::CommandUnsubscribe => "Unsubscribe from the newsletter".to_string(),
+            Self::CommandUpdates => "Toggle title and category change notifications".to_string(),
+            Self::CommandList => "Show the chat's subscriptions".to_string(),
+            Self::CommandLive => "Show which of your streamers are live".to_string(),
+            Self::CommandTemplate => "Customize the live notification of a streamer".to_string(),
+            Self::CommandPreview => "Preview the live notification of a streamer".to_string(),
+            Self::CommandLanguage => "Change the language of the bot".to_string(),
+            Self::CommandFavorite => "Announce a streamer even during quiet hours".to_string(),
+            Self::CommandQuiet => "Set quiet hours".to_string(),
+            Self::CommandTimezone => "Set the timezone of quiet hours".to_string(),
+            Self::CommandStats => "Show bot statistics".to_string(),
+            Self::CommandBroadcast => "Send an announcement to every chat".to_string(),
+            Self::CommandWhois => "Show the subscriptions of a user".to_string(),
+        }
+    }
+
+    fn ru(&self) -> String {
+        match self {
+            Self::Help => "Добро пожаловать!\n\n\
+                Этот бот присылает уведомления о начале трансляций."
+                .to_string(),
+            Self::NotAdmin => "Только администраторы чата могут менять здесь подписки.".to_string(),
+            Self::NotSubscribed => "Вы не подписаны на этого стримера!".to_string(),
+
+            Self::SubscribeUsage => {
+                "Использование: /subscribe <логин или ссылка twitch.tv> [ещё логины...]".to_string()
+            }
+            Self::SubscribedTo(names) => format!("Вы подписались на {}!", names),
+            Self::SubscribeConfirm(name) => {
+                format!("Получать здесь уведомления, когда <b>{}</b> в эфире?", name)
+            }
+            Self::StreamerNotFound => "Стример не найден!".to_string(),
+            Self::LoginNotFound(login) => format!("Стример {} не найден!", login),
+            Self::LoginNotFoundSuggestions(login) => {
+                format!("Стример {} не найден! Может быть, один из этих?", login)
+            }
+            Self::Unsubscribed => "Вы отписались!".to_string(),
+            Self::UnsubscribedFrom(name) => format!("Вы отписались от {}!", name),
+            Self::UpdatesEnabled => {
+                "Уведомления о смене названия и категории включены!".to_string()
+            }
+            Self::UpdatesDisabled => {
+                "Уведомления о смене названия и категории выключены!".to_string()
+            }
+            Self::FavoriteEnabled => {
+                "Добавлено в избранное, о его стримах будет сообщаться даже в тихие часы!"
+                    .to_string()
+            }
+            Self::FavoriteDisabled => "Убрано из избранного!".to_string(),
+
+            Self::TemplateUsage(placeholders) => format!(
+                "Использование: /template <логин> <текст>, или /template <логин>, чтобы вернуть шаблон по умолчанию.\n\n\
+                 В тексте можно использовать HTML и подстановки: {}",
+                placeholders
+            ),
+            Self::TemplateReset => "Вернули шаблон по умолчанию!".to_string(),
+            Self::TemplateSaved(login) => format!(
+                "Шаблон сохранён! Посмотреть, как он выглядит: /preview {}",
+                login
+            ),
+            Self::TemplateInvalid(err) => format!("Неправильный шаблон: {}", err),
+            Self::TemplateRejected(err) => format!("Telegram не принял шаблон: {}", err),
+            Self::ExampleTitle => "Пример названия трансляции".to_string(),
+
+            Self::NoSubscriptions => {
+                "У вас пока нет подписок. Добавьте первую через /subscribe!".to_string()
+            }
+            Self::ListHeader { page, pages } => {
+                format!("Ваши подписки (страница {} из {}):", page, pages)
+            }
+            Self::NobodyLive => "Сейчас никто из ваших стримеров не в эфире.".to_string(),
+
+            Self::LanguageUsage => "Выберите язык бота:".to_string(),
+            Self::LanguageSet => "Теперь бот говорит по-русски!".to_string(),
+
+            Self::QuietUsage => {
+                "Использование: /quiet <с>-<до> [silent|digest], например /quiet 23:00-08:00, \
+                 или /quiet off.\n\n\
+                 В тихие часы уведомления о стримах приходят без звука, а с digest \
+                 откладываются и приходят одним сообщением, когда тихие часы закончатся. \
+                 Об избранных стримерах (/favorite) сообщается как обычно."
+                    .to_string()
+            }
+            Self::QuietSilent { hours, timezone } => format!(
+                "Тихие часы: {} ({}), уведомления будут приходить без звука.",
+                hours, timezone
+            ),
+            Self::QuietDigest { hours, timezone } => format!(
+                "Тихие часы: {} ({}), уведомления придут одним сообщением после них.",
+                hours, timezone
+            ),
+            Self::QuietOff => "Тихие часы выключены!".to_string(),
+            Self::TimezoneUsage(timezone) => format!(
+                "Использование: /timezone <название>, например /timezone Europe/Moscow.\n\nСейчас часовой пояс {}.",
+                timezone
+            ),
+            Self::TimezoneSet(timezone) => format!("Часовой пояс: {}!", timezone),
+            Self::TimezoneInvalid(timezone) => format!("Неизвестный часовой пояс {}!", timezone),
+
+            Self::Stats {
+                chats,
+                subscriptions,
+                streamers,
+                registrations,
+                notifications_today,
+                removed_chats,
+            } => format!(
+                "Чатов: {}\n\
+                 Подписок: {}\n\
+                 Стримеров: {}\n\
+                 Активных подписок EventSub: {}\n\
+                 Уведомлений за сегодня: {}\n\
+                 Удалено чатов, заблокировавших бота: {}",
+                chats, subscriptions, streamers, registrations, notifications_today, removed_chats
+            ),
+            Self::BroadcastUsage => "Использование: /broadcast <текст>".to_string(),
+            Self::BroadcastProgress {
+                sent,
+                failed,
+                total,
+            } => format!(
+                "Рассылка... отправлено {} из {}, ошибок: {}",
+                sent, total, failed
+            ),
+            Self::BroadcastDone {
+                sent,
+                failed,
+                total,
+            } => format!(
+                "Рассылка завершена: отправлено {} из {}, ошибок: {}",
+                sent, total, failed
+            ),
+            Self::WhoisUsage => "Использование: /whois <id пользователя или чата>".to_string(),
+            Self::WhoisHeader {
+                chat_id,
+                subscriptions,
+            } => format!("У чата {} подписок: {}", chat_id, subscriptions),
+            Self::WhoisInactive => "Чат заблокировал или удалил бота".to_string(),
+
+            Self::IsLive(name) => format!("🔴 <b>{}</b> в эфире!", name),
+            Self::IsOffline(name) => format!("⚫️ <b>{}</b> не в эфире", name),
+            Self::Offline => "Не в эфире".to_string(),
+            Self::Viewers(viewers) => format!("👀 {} зрителей", viewers),
+            Self::EndedStream(name) => format!("⚫️ <b>{}</b> закончил трансляцию", name),
+            Self::EndedStreamLasted(name, lasted) => format!(
+                "⚫️ <b>{}</b> закончил трансляцию (длилась {})",
+                name, lasted
+            ),
+            Self::UpdatedStream(name) => format!("✏️ <b>{}</b> обновил трансляцию", name),
+            Self::Title => "Название".to_string(),
+            Self::Category => "Категория".to_string(),
+            Self::Raided { from, to, viewers } => format!(
+                "🚀 <b>{}</b> отправил рейд на <b>{}</b> с {} зрителями",
+                from, to, viewers
+            ),
+            Self::ChannelRemoved(name) => format!(
+                "⚠️ <b>{}</b> больше нет на Twitch, подписка приостановлена",
+                name
+            ),
+            Self::Digest => "🌙 Начали стрим в тихие часы:".to_string(),
+            Self::DigestEnded => "уже закончился".to_string(),
+            Self::WatchOnTwitch => "Смотреть на Twitch".to_string(),
+            Self::Watch(name) => format!("Смотреть {}", name),
+            Self::SubscribeTo(name) => format!("Подписаться на {}", name),
+            Self::Subscribe => "🔔 Подписаться".to_string(),
+            Self::Duration { hours: 0, minutes } => format!("{} мин", minutes),
+            Self::Duration { hours, minutes } => format!("{} ч {} мин", hours, minutes),
+
+            Self::CommandStart => "Запустить бота".to_string(),
+            Self::CommandHelp => "Показать справку".to_string(),
+            Self::CommandSubscribe => {
+                "Подписаться на стримеров по логину или ссылке twitch.tv".to_string()
+            }
+            Self::CommandUnsubscribe => "Отписаться от стримера".to_string(),
+            Self::CommandUpdates => "Уведомления о смене названия и категории".to_string(),
+            Self::CommandList => "Показать подписки чата".to_string(),
+            Self::CommandLive => "Показать, кто из стримеров в эфире".to_string(),
+            Self::CommandTemplate => "Настроить уведомление о стриме".to_string(),
+            Self::CommandPreview => "Посмотреть уведомление о стриме".to_string(),
+            Self::CommandLanguage => "Сменить язык бота".to_string(),
+            Self::CommandFavorite => "Сообщать о стримере даже в тихие часы".to_string(),
+            Self::CommandQuiet => "Настроить тихие часы".to_string(),
+            Self::CommandTimezone => "Часовой пояс тихих часов".to_string(),
+            Self::CommandStats => "Статистика бота".to_string(),
+            Self::CommandBroadcast => "Разослать объявление во все чаты".to_string(),
+            Self::CommandWhois => "Подписки пользователя".to_string(),
+        }
+    }
+}
@@ -1,14 +1,24 @@
 pub mod config;
+pub mod i18n;
+pub mod migrations;
+pub mod notifier;
+pub mod quiet_hours;
 pub mod repositories;
 pub mod subscription_manager;
 pub mod telegram_bot;
+pub mod templates;
+pub mod twitch_client;
+pub mod twitch_poller;
 pub mod twitch_webhook;
+pub mod twitch_websocket;
 pub mod web_app;
 
 use std::sync::Arc;
 
+use migrations::{migrate_chat_ids, migrate_streamer_logins};
 use subscription_manager::SubscriptionManager;
 use telegram_bot::start_telegram_bot;
+use twitch_client::TwitchClient;
 use twitch_webhook::start_twitch_webhook;
 
 #[tokio::main]
@@ -17,15 +27,22 @@ async fn main() {
         .with_max_level(tracing::Level::DEBUG)
         .init();
 
+    let twitch_client = Arc::new(TwitchClient::new().await.unwrap());
+
+    migrate_streamer_logins(&twitch_client).await.unwrap();
+    migrate_chat_ids().await.unwrap();
+
     let subscription_manager = Arc::new(SubscriptionManager::new());
 
     subscription_manager.load().await.unwrap();
 
     tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
 
-    let (_, webhook_result) = tokio::join!(
-        start_telegram_bot(subscription_manager.clone()),
-        start_twitch_webhook(subscription_manager)
+    let (_, webhook_result, _, _) = tokio::join!(
+        start_telegram_bot(subscription_manager.clone(), twitch_client.clone()),
+        start_twitch_webhook(subscription_manager, twitch_client.clone()),
+        twitch_client.keep_app_access_token_fresh(),
+        twitch_client.keep_user_access_token_fresh()
     );
 
     if let Err(e) = webhook_result {
@@ -0,0 +1,48 @@
+use crate::{repositories::subscriptions::SubscriptionRepository, twitch_client::TwitchClient};
+
+/// Converts subscriptions stored by free-text login into ones keyed by
+/// broadcaster id. Logins Twitch doesn't know are dropped, they could
+/// never have been delivered.
+pub async fn migrate_streamer_logins(twitch_client: &TwitchClient) -> Result<(), eyre::Report> {
+    let legacy = SubscriptionRepository::all_legacy().await?;
+
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Migrating {} legacy subscriptions", legacy.len());
+
+    for sub in legacy {
+        let login = sub.streamer.trim().to_lowercase();
+
+        let broadcaster = if login.is_empty() {
+            None
+        } else {
+            twitch_client.get_broadcaster_by_login(&login).await?
+        };
+
+        if broadcaster.is_none() {
+            tracing::warn!(
+                "Dropping subscription of {} to unknown streamer {}",
+                sub.telegram_user_id,
+                sub.streamer
+            );
+        }
+
+        SubscriptionRepository::migrate_legacy(sub, broadcaster).await?;
+    }
+
+    Ok(())
+}
+
+/// Keys subscriptions by chat instead of by user, so groups and channels can
+/// have their own.
+pub async fn migrate_chat_ids() -> Result<(), eyre::Report> {
+    let migrated = SubscriptionRepository::migrate_chat_ids().await?;
+
+    if migrated > 0 {
+        tracing::info!("Moved {} subscriptions onto chat ids", migrated);
+    }
+
+    Ok(())
+}
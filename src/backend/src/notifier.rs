@@ -0,0 +1,1006 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use teloxide::{
+    ApiError, RequestError,
+    payloads::{
+        EditMessageCaptionSetters as _, EditMessageTextSetters as _, SendMessageSetters as _,
+        SendPhotoSetters as _,
+    },
+    prelude::Requester as _,
+    types::{
+        ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile, Message, MessageId,
+        ParseMode, ThreadId,
+    },
+    utils::html,
+};
+use tokio::sync::RwLock;
+use twitch_api::helix::streams::Stream;
+
+use crate::{
+    config::CONFIG,
+    i18n::{Language, Text},
+    quiet_hours::QuietMode,
+    repositories::{
+        chats::{ChatRepository, ChatSettings, DigestEntry},
+        stats::{self, StatsRepository},
+        subscriptions::{Subscription, SubscriptionRepository},
+    },
+    subscription_manager::SubscriptionManager,
+    telegram_bot::{Bot, SUBSCRIBE_CALLBACK_PREFIX, get_telegram_bot},
+    templates::{self, TemplateValues},
+    twitch_client::{Broadcaster, TwitchClient},
+};
+
+struct LiveMessage {
+    chat_id: ChatId,
+    message_id: MessageId,
+    is_photo: bool,
+}
+
+/// What we know about a stream between its online and offline events.
+struct LiveSession {
+    started_at: Option<DateTime<Utc>>,
+    /// For the Watch button, which editing the messages would drop otherwise
+    broadcaster_login: String,
+    messages: Vec<LiveMessage>,
+}
+
+/// Title and category, remembered to show what a channel.update changed.
+#[derive(Clone)]
+struct ChannelState {
+    title: String,
+    category: String,
+}
+
+/// Settings of the chats an event goes to, chats without any use defaults.
+struct RecipientSettings(HashMap<i64, ChatSettings>);
+
+impl RecipientSettings {
+    fn language(&self, chat_id: i64) -> Language {
+        self.0
+            .get(&chat_id)
+            .map(ChatSettings::language)
+            .unwrap_or_default()
+    }
+
+    fn is_quiet(&self, chat_id: i64) -> bool {
+        self.0
+            .get(&chat_id)
+            .is_some_and(|settings| settings.is_quiet(Utc::now()))
+    }
+
+    fn quiet_mode(&self, chat_id: i64) -> QuietMode {
+        self.0
+            .get(&chat_id)
+            .map(|settings| settings.quiet_mode)
+            .unwrap_or_default()
+    }
+}
+
+/// Fans stream events out to the Telegram subscribers of a broadcaster.
+pub struct Notifier {
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+    bot: Bot,
+    live_sessions: RwLock<HashMap<String, LiveSession>>,
+    channel_states: RwLock<HashMap<String, ChannelState>>,
+}
+
+impl Notifier {
+    const STREAM_FETCH_ATTEMPTS: u32 = 3;
+    const STREAM_FETCH_DELAY: Duration = Duration::from_secs(5);
+    const DIGEST_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(
+        subscription_manager: Arc<SubscriptionManager>,
+        twitch_client: Arc<TwitchClient>,
+    ) -> Self {
+        Self {
+            subscription_manager,
+            twitch_client,
+            bot: get_telegram_bot(),
+            live_sessions: RwLock::new(HashMap::new()),
+            channel_states: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Helix may lag behind EventSub for a few seconds after a stream starts.
+    async fn fetch_stream(&self, broadcaster_id: &str) -> Option<Stream> {
+        for attempt in 1..=Self::STREAM_FETCH_ATTEMPTS {
+            match self
+                .twitch_client
+                .get_streams(&[broadcaster_id.to_string()])
+                .await
+            {
+                Ok(streams) if !streams.is_empty() => return streams.into_iter().next(),
+                Ok(_) => {}
+                Err(err) => tracing::error!("Failed to get stream {}: {:?}", broadcaster_id, err),
+            }
+
+            if attempt < Self::STREAM_FETCH_ATTEMPTS {
+                tokio::time::sleep(Self::STREAM_FETCH_DELAY).await;
+            }
+        }
+
+        None
+    }
+
+    async fn send_live_message(
+        &self,
+        chat_id: ChatId,
+        thread_id: Option<ThreadId>,
+        text: &str,
+        photo_url: Option<&url::Url>,
+        keyboard: InlineKeyboardMarkup,
+        silent: bool,
+    ) -> Result<LiveMessage, RequestError> {
+        if let Some(photo_url) = photo_url {
+            let mut request = self
+                .bot
+                .send_photo(chat_id, InputFile::url(photo_url.clone()))
+                .caption(text)
+                .parse_mode(ParseMode::Html)
+                .reply_markup(keyboard.clone())
+                .disable_notification(silent);
+
+            if let Some(thread_id) = thread_id {
+                request = request.message_thread_id(thread_id);
+            }
+
+            let result = request.await;
+
+            match result {
+                Ok(message) => {
+                    Self::count_notification().await;
+                    return Ok(Self::live_message(message, true));
+                }
+                Err(err) if is_chat_unreachable(&err) => {
+                    self.handle_send_error(chat_id, &err).await;
+                    return Err(err);
+                }
+                Err(err) => tracing::warn!("Failed to send photo to {}: {:?}", chat_id, err),
+            }
+        }
+
+        let message = self
+            .send_text(chat_id, thread_id, text.to_string(), Some(keyboard), silent)
+            .await?;
+
+        Ok(Self::live_message(message, false))
+    }
+
+    /// Sends an HTML message into the chat, or into its forum topic.
+    async fn send_text(
+        &self,
+        chat_id: ChatId,
+        thread_id: Option<ThreadId>,
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+        silent: bool,
+    ) -> Result<Message, RequestError> {
+        let mut request = self
+            .bot
+            .send_message(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .disable_notification(silent);
+
+        if let Some(thread_id) = thread_id {
+            request = request.message_thread_id(thread_id);
+        }
+
+        if let Some(keyboard) = keyboard {
+            request = request.reply_markup(keyboard);
+        }
+
+        let result = request.await;
+
+        match result.as_ref() {
+            Ok(_) => Self::count_notification().await,
+            Err(err) => self.handle_send_error(chat_id, err).await,
+        }
+
+        result
+    }
+
+    async fn count_notification() {
+        let counter = stats::notifications_sent(Utc::now().date_naive());
+
+        if let Err(err) = StatsRepository::increment(&counter).await {
+            tracing::error!("Failed to count notification: {:?}", err);
+        }
+    }
+
+    /// Keeps the subscriptions in line with what Telegram says about the
+    /// chat, instead of failing on it with every stream.
+    async fn handle_send_error(&self, chat_id: ChatId, err: &RequestError) {
+        if let RequestError::MigrateToChatId(new_chat_id) = err {
+            self.subscription_manager
+                .migrate_chat(chat_id.0, new_chat_id.0)
+                .await;
+        } else if is_chat_unreachable(err) {
+            tracing::info!("Removing unreachable chat {}: {}", chat_id, err);
+
+            self.subscription_manager
+                .remove_chat(chat_id.0, err.to_string())
+                .await;
+        }
+    }
+
+    fn live_message(message: Message, is_photo: bool) -> LiveMessage {
+        LiveMessage {
+            chat_id: message.chat.id,
+            message_id: message.id,
+            is_photo,
+        }
+    }
+
+    async fn recipient_settings(&self, chat_ids: &[i64]) -> RecipientSettings {
+        match ChatRepository::get_many(chat_ids).await {
+            Ok(chats) => {
+                RecipientSettings(chats.into_iter().map(|chat| (chat.chat_id, chat)).collect())
+            }
+            Err(err) => {
+                tracing::error!("Failed to load chat settings: {:?}", err);
+                RecipientSettings(HashMap::new())
+            }
+        }
+    }
+
+    async fn recipients(&self, broadcaster_id: &str) -> Vec<(ChatId, Option<ThreadId>)> {
+        self.subscription_manager
+            .subscriptions
+            .read()
+            .await
+            .get(broadcaster_id)
+            .map(|chats| {
+                chats
+                    .iter()
+                    .map(|(chat_id, message_thread_id)| {
+                        (ChatId(*chat_id), thread_id(*message_thread_id))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub async fn stream_online(
+        &self,
+        broadcaster_id: String,
+        broadcaster_login: String,
+        broadcaster_name: String,
+        started_at: String,
+    ) {
+        let started_at = DateTime::parse_from_rfc3339(&started_at)
+            .ok()
+            .map(|v| v.with_timezone(&Utc));
+
+        // EventSub and the poller may both announce the same stream
+        {
+            let mut live_sessions = self.live_sessions.write().await;
+
+            if live_sessions
+                .get(&broadcaster_id)
+                .is_some_and(|session| session.started_at == started_at)
+            {
+                return;
+            }
+
+            live_sessions.insert(
+                broadcaster_id.clone(),
+                LiveSession {
+                    started_at,
+                    broadcaster_login: broadcaster_login.clone(),
+                    messages: Vec::new(),
+                },
+            );
+        }
+
+        let mut messages = Vec::new();
+
+        let stream = self.fetch_stream(&broadcaster_id).await;
+
+        if let Some(stream) = stream.as_ref() {
+            self.channel_states.write().await.insert(
+                broadcaster_id.clone(),
+                ChannelState {
+                    title: stream.title.clone(),
+                    category: stream.game_name.clone(),
+                },
+            );
+        }
+
+        let photo_url = stream.as_ref().and_then(thumbnail_url);
+
+        let subscriptions = match SubscriptionRepository::all_by_broadcaster(&broadcaster_id).await
+        {
+            Ok(subs) => subs
+                .into_iter()
+                .map(|sub| (sub.chat_id, sub))
+                .collect::<HashMap<i64, Subscription>>(),
+            Err(err) => {
+                tracing::error!(
+                    "Failed to load subscriptions of {}: {:?}",
+                    broadcaster_id,
+                    err
+                );
+                HashMap::new()
+            }
+        };
+        let values = template_values(&broadcaster_login, &broadcaster_name, stream.as_ref());
+
+        let recipients = self.recipients(&broadcaster_id).await;
+        let settings = self
+            .recipient_settings(&recipients.iter().map(|v| v.0.0).collect::<Vec<_>>())
+            .await;
+
+        for (chat_id, thread_id) in recipients {
+            let subscription = subscriptions.get(&chat_id.0);
+
+            let silent = settings.is_quiet(chat_id.0) && !subscription.is_some_and(|v| v.favorite);
+
+            if silent && settings.quiet_mode(chat_id.0) == QuietMode::Digest {
+                let entry = DigestEntry {
+                    broadcaster_id: broadcaster_id.clone(),
+                    broadcaster_login: broadcaster_login.clone(),
+                    broadcaster_display_name: broadcaster_name.clone(),
+                    title: stream.as_ref().map(|stream| stream.title.clone()),
+                    message_thread_id: thread_id.map(|thread_id| thread_id.0.0),
+                    ended: false,
+                };
+
+                if let Err(err) = ChatRepository::push_digest(chat_id.0, entry).await {
+                    tracing::error!("Failed to hold notification for {}: {:?}", chat_id, err);
+                }
+
+                continue;
+            }
+
+            let language = settings.language(chat_id.0);
+
+            let default_text = live_message_text(language, &broadcaster_name, stream.as_ref());
+            let keyboard = watch_keyboard(language, &broadcaster_login);
+
+            let photo_url = photo_url.as_ref();
+
+            let send = |text: String| {
+                let keyboard = keyboard.clone();
+
+                async move {
+                    self.send_live_message(chat_id, thread_id, &text, photo_url, keyboard, silent)
+                        .await
+                }
+            };
+
+            let text = match subscription.and_then(|v| v.template.as_ref()) {
+                Some(template) => templates::render(template, &values)
+                    .map_err(|err| {
+                        tracing::warn!("Failed to render template for {}: {}", chat_id, err);
+                    })
+                    .ok(),
+                None => None,
+            };
+
+            let result = match text {
+                Some(text) => {
+                    send_template(
+                        text,
+                        |_| {
+                            tracing::warn!(
+                                "Template for {} was rejected, sending the default",
+                                chat_id
+                            );
+                            default_text.clone()
+                        },
+                        send,
+                    )
+                    .await
+                }
+                None => send(default_text.clone()).await,
+            };
+
+            match result {
+                Ok(message) => messages.push(message),
+                Err(err) => tracing::error!("Failed to send message to {}: {:?}", chat_id, err),
+            }
+        }
+
+        if let Some(session) = self.live_sessions.write().await.get_mut(&broadcaster_id) {
+            session.messages.extend(messages);
+        }
+    }
+
+    pub async fn stream_offline(&self, broadcaster_id: String, broadcaster_name: String) {
+        let session = self.live_sessions.write().await.remove(&broadcaster_id);
+
+        // Chats still holding the live notification learn about the end from
+        // their digest, instead of getting an end without a start
+        let held = match ChatRepository::end_digest_entries(&broadcaster_id).await {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to end digest entries of {}: {:?}",
+                    broadcaster_id,
+                    err
+                );
+                Vec::new()
+            }
+        };
+
+        let lasted = session
+            .as_ref()
+            .and_then(|session| session.started_at)
+            .map(|started_at| Utc::now() - started_at);
+
+        let broadcaster_name = html::escape(&broadcaster_name);
+
+        let text = |language: Language| match lasted {
+            Some(lasted) => {
+                Text::EndedStreamLasted(&broadcaster_name, &format_duration(language, lasted))
+                    .localize(language)
+            }
+            None => Text::EndedStream(&broadcaster_name).localize(language),
+        };
+
+        if let Some(session) = session.filter(|_| CONFIG.edit_live_message_on_offline) {
+            let settings = self
+                .recipient_settings(
+                    &session
+                        .messages
+                        .iter()
+                        .map(|message| message.chat_id.0)
+                        .collect::<Vec<_>>(),
+                )
+                .await;
+
+            for message in session.messages {
+                let language = settings.language(message.chat_id.0);
+                let text = text(language);
+                let keyboard = watch_keyboard(language, &session.broadcaster_login);
+
+                let result = if message.is_photo {
+                    self.bot
+                        .edit_message_caption(message.chat_id, message.message_id)
+                        .caption(text)
+                        .parse_mode(ParseMode::Html)
+                        .reply_markup(keyboard)
+                        .await
+                } else {
+                    self.bot
+                        .edit_message_text(message.chat_id, message.message_id, text)
+                        .parse_mode(ParseMode::Html)
+                        .reply_markup(keyboard)
+                        .await
+                };
+
+                if let Err(err) = result {
+                    tracing::error!("Failed to edit message in {}: {:?}", message.chat_id, err);
+                }
+            }
+
+            return;
+        }
+
+        let recipients = self.recipients(&broadcaster_id).await;
+        let settings = self
+            .recipient_settings(&recipients.iter().map(|v| v.0.0).collect::<Vec<_>>())
+            .await;
+
+        for (chat_id, thread_id) in recipients {
+            if held.contains(&chat_id.0) {
+                continue;
+            }
+
+            let text = text(settings.language(chat_id.0));
+
+            if let Err(err) = self
+                .send_text(chat_id, thread_id, text, None, settings.is_quiet(chat_id.0))
+                .await
+            {
+                tracing::error!("Failed to send message to {}: {:?}", chat_id, err);
+            }
+        }
+    }
+
+    /// Whether the broadcaster is streaming, also for streams that started
+    /// before a restart.
+    async fn is_live(&self, broadcaster_id: &str) -> bool {
+        if self.live_sessions.read().await.contains_key(broadcaster_id) {
+            return true;
+        }
+
+        match self
+            .twitch_client
+            .get_streams_cached(&[broadcaster_id.to_string()])
+            .await
+        {
+            Ok(streams) => !streams.is_empty(),
+            Err(err) => {
+                tracing::error!("Failed to get stream {}: {:?}", broadcaster_id, err);
+                false
+            }
+        }
+    }
+
+    /// Remembers the title and category of every followed channel, so the
+    /// first channel.update after a restart has something to compare with.
+    pub async fn load_channel_states(&self) {
+        let broadcaster_ids = self
+            .subscription_manager
+            .subscriptions
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let channels = match self.twitch_client.get_channels(&broadcaster_ids).await {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!("Failed to load channel states: {:?}", err);
+                return;
+            }
+        };
+
+        let mut channel_states = self.channel_states.write().await;
+
+        for channel in channels {
+            channel_states
+                .entry(channel.broadcaster_id.to_string())
+                .or_insert(ChannelState {
+                    title: channel.title.to_string(),
+                    category: channel.game_name.to_string(),
+                });
+        }
+    }
+
+    pub async fn channel_update(
+        &self,
+        broadcaster_id: String,
+        broadcaster_name: String,
+        title: String,
+        category: String,
+    ) {
+        let current = ChannelState { title, category };
+
+        let previous = match self
+            .channel_states
+            .write()
+            .await
+            .insert(broadcaster_id.clone(), current.clone())
+        {
+            Some(v) => v,
+            // Nothing to compare with, e.g. a channel followed since the start
+            None => return,
+        };
+
+        // channel.update also fires for language and content label changes
+        if previous.title == current.title && previous.category == current.category {
+            return;
+        }
+
+        // Only changes in the middle of a stream are news
+        if !self.is_live(&broadcaster_id).await {
+            return;
+        }
+
+        let subscriptions = match SubscriptionRepository::all_by_broadcaster(&broadcaster_id).await
+        {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to load subscriptions of {}: {:?}",
+                    broadcaster_id,
+                    err
+                );
+                return;
+            }
+        };
+
+        let subscriptions = subscriptions
+            .into_iter()
+            .filter(|sub| sub.active && sub.notify_channel_updates)
+            .collect::<Vec<_>>();
+
+        let settings = self
+            .recipient_settings(
+                &subscriptions
+                    .iter()
+                    .map(|sub| sub.chat_id)
+                    .collect::<Vec<_>>(),
+            )
+            .await;
+
+        for sub in subscriptions {
+            let language = settings.language(sub.chat_id);
+
+            let text = channel_update_text(language, &broadcaster_name, &previous, &current);
+
+            if let Err(err) = self
+                .send_text(
+                    ChatId(sub.chat_id),
+                    thread_id(sub.message_thread_id),
+                    text,
+                    None,
+                    settings.is_quiet(sub.chat_id),
+                )
+                .await
+            {
+                tracing::error!("Failed to send message to {}: {:?}", sub.chat_id, err);
+            }
+        }
+    }
+
+    pub async fn channel_raid(
+        &self,
+        broadcaster_id: String,
+        broadcaster_name: String,
+        target: Broadcaster,
+        viewers: i64,
+    ) {
+        let broadcaster_name = html::escape(&broadcaster_name);
+        let target_name = html::escape(&target.display_name);
+
+        let target_subscribers = self
+            .subscription_manager
+            .subscriptions
+            .read()
+            .await
+            .get(&target.id)
+            .cloned()
+            .unwrap_or_default();
+
+        let recipients = self.recipients(&broadcaster_id).await;
+        let settings = self
+            .recipient_settings(&recipients.iter().map(|v| v.0.0).collect::<Vec<_>>())
+            .await;
+
+        for (chat_id, thread_id) in recipients {
+            let language = settings.language(chat_id.0);
+
+            let text = Text::Raided {
+                from: &broadcaster_name,
+                to: &target_name,
+                viewers,
+            }
+            .localize(language);
+
+            let mut buttons = vec![InlineKeyboardButton::url(
+                Text::Watch(&target.display_name).localize(language),
+                channel_url(&target.login),
+            )];
+
+            if !target_subscribers.contains_key(&chat_id.0) {
+                buttons.push(InlineKeyboardButton::callback(
+                    Text::SubscribeTo(&target.display_name).localize(language),
+                    format!("{}{}", SUBSCRIBE_CALLBACK_PREFIX, target.id),
+                ));
+            }
+
+            if let Err(err) = self
+                .send_text(
+                    chat_id,
+                    thread_id,
+                    text,
+                    Some(InlineKeyboardMarkup::new([buttons])),
+                    settings.is_quiet(chat_id.0),
+                )
+                .await
+            {
+                tracing::error!("Failed to send message to {}: {:?}", chat_id, err);
+            }
+        }
+    }
+
+    /// Tells subscribers that the channel was deleted or banned and pauses
+    /// their subscriptions.
+    pub async fn channel_removed(&self, broadcaster_id: String) {
+        // Every event type gets revoked, only the first one does the work
+        let chats = match self
+            .subscription_manager
+            .subscriptions
+            .write()
+            .await
+            .remove(&broadcaster_id)
+        {
+            Some(v) => v,
+            None => return,
+        };
+
+        let subscriptions = match SubscriptionRepository::all_by_broadcaster(&broadcaster_id).await
+        {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to load subscriptions of {}: {:?}",
+                    broadcaster_id,
+                    err
+                );
+                return;
+            }
+        };
+
+        if let Err(err) = SubscriptionRepository::deactivate_by_broadcaster(&broadcaster_id).await {
+            tracing::error!(
+                "Failed to deactivate subscriptions of {}: {:?}",
+                broadcaster_id,
+                err
+            );
+        }
+
+        let broadcaster_name = subscriptions
+            .first()
+            .map(|sub| sub.broadcaster_display_name.clone())
+            .unwrap_or(broadcaster_id);
+
+        let broadcaster_name = html::escape(&broadcaster_name);
+
+        let settings = self
+            .recipient_settings(&chats.keys().copied().collect::<Vec<_>>())
+            .await;
+
+        for (chat_id, message_thread_id) in chats {
+            let language = settings.language(chat_id);
+
+            if let Err(err) = self
+                .send_text(
+                    ChatId(chat_id),
+                    thread_id(message_thread_id),
+                    Text::ChannelRemoved(&broadcaster_name).localize(language),
+                    None,
+                    settings.is_quiet(chat_id),
+                )
+                .await
+            {
+                tracing::error!("Failed to send message to {}: {:?}", chat_id, err);
+            }
+        }
+    }
+
+    /// Sends the live notifications held back during quiet hours, one
+    /// message per chat and forum topic.
+    async fn deliver_digest(&self, chat: &ChatSettings) -> Result<(), eyre::Report> {
+        let entries = ChatRepository::take_digest(chat.chat_id).await?;
+
+        let mut topics: Vec<(Option<i32>, Vec<DigestEntry>)> = Vec::new();
+
+        for entry in entries {
+            match topics
+                .iter_mut()
+                .find(|(message_thread_id, _)| *message_thread_id == entry.message_thread_id)
+            {
+                Some((_, topic)) => topic.push(entry),
+                None => topics.push((entry.message_thread_id, vec![entry])),
+            }
+        }
+
+        let mut topics = topics.into_iter();
+
+        while let Some((message_thread_id, entries)) = topics.next() {
+            let language = chat.language();
+
+            let mut text = Text::Digest.localize(language);
+
+            for entry in entries.iter() {
+                text.push_str(&format!(
+                    "\n\n{} <a href=\"{}\">{}</a>",
+                    if entry.ended { "⚫" } else { "🔴" },
+                    channel_url(&entry.broadcaster_login),
+                    html::escape(&entry.broadcaster_display_name)
+                ));
+
+                if let Some(title) = entry.title.as_ref() {
+                    text.push_str(&format!(" — {}", html::escape(title)));
+                }
+
+                if entry.ended {
+                    text.push_str(&format!(" ({})", Text::DigestEnded.localize(language)));
+                }
+            }
+
+            let err = match self
+                .send_text(
+                    ChatId(chat.chat_id),
+                    thread_id(message_thread_id),
+                    text,
+                    None,
+                    false,
+                )
+                .await
+            {
+                Ok(_) => continue,
+                Err(err) => err,
+            };
+
+            // Whatever wasn't sent waits for the next delivery, unless the
+            // chat is gone
+            let chat_id = match &err {
+                RequestError::MigrateToChatId(new_chat_id) => Some(new_chat_id.0),
+                err if is_chat_unreachable(err) => None,
+                _ => Some(chat.chat_id),
+            };
+
+            if let Some(chat_id) = chat_id {
+                let unsent = entries
+                    .into_iter()
+                    .chain(topics.flat_map(|(_, entries)| entries))
+                    .collect::<Vec<_>>();
+
+                ChatRepository::restore_digest(chat_id, &unsent).await?;
+            }
+
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn deliver_digests(&self) {
+        let mut interval = tokio::time::interval(Self::DIGEST_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let chats = match ChatRepository::all_with_digest().await {
+                Ok(v) => v,
+                Err(err) => {
+                    tracing::error!("Failed to load digests: {:?}", err);
+                    continue;
+                }
+            };
+
+            for chat in chats.iter().filter(|chat| !chat.is_quiet(Utc::now())) {
+                if let Err(err) = self.deliver_digest(chat).await {
+                    tracing::error!("Failed to send digest to {}: {:?}", chat.chat_id, err);
+                }
+            }
+        }
+    }
+}
+
+/// Errors meaning the chat is gone for the bot: blocked, deleted or left.
+pub fn is_chat_unreachable(err: &RequestError) -> bool {
+    match err {
+        RequestError::Api(
+            ApiError::BotBlocked
+            | ApiError::ChatNotFound
+            | ApiError::UserDeactivated
+            | ApiError::GroupDeactivated
+            | ApiError::BotKicked
+            | ApiError::BotKickedFromSupergroup
+            | ApiError::CantInitiateConversation,
+        ) => true,
+        // Variants teloxide doesn't know, e.g. for basic groups and channels
+        RequestError::Api(ApiError::Unknown(description)) => {
+            description.starts_with("Forbidden: bot was kicked")
+                || description.starts_with("Forbidden: bot is not a member")
+        }
+        _ => false,
+    }
+}
+
+/// Sends a text rendered from a template. Broken HTML in a template only
+/// shows up once Telegram parses it, then what `fallback` makes of the error
+/// is sent instead.
+pub async fn send_template<T, F, Fut>(
+    text: String,
+    fallback: impl FnOnce(&RequestError) -> String,
+    send: F,
+) -> Result<T, RequestError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    match send(text).await {
+        Err(err @ RequestError::Api(ApiError::CantParseEntities(_))) => send(fallback(&err)).await,
+        result => result,
+    }
+}
+
+fn channel_update_text(
+    language: Language,
+    broadcaster_name: &str,
+    previous: &ChannelState,
+    current: &ChannelState,
+) -> String {
+    let mut text = Text::UpdatedStream(&html::escape(broadcaster_name)).localize(language);
+
+    let changes = [
+        (
+            Text::Title.localize(language),
+            &previous.title,
+            &current.title,
+        ),
+        (
+            Text::Category.localize(language),
+            &previous.category,
+            &current.category,
+        ),
+    ];
+
+    for (name, previous, current) in changes {
+        if previous != current {
+            text.push_str(&format!(
+                "\n{}: <s>{}</s> → {}",
+                name,
+                html::escape(previous),
+                html::escape(current)
+            ));
+        }
+    }
+
+    text
+}
+
+pub fn live_message_text(
+    language: Language,
+    broadcaster_name: &str,
+    stream: Option<&Stream>,
+) -> String {
+    let mut text = Text::IsLive(&html::escape(broadcaster_name)).localize(language);
+
+    if let Some(stream) = stream {
+        text.push_str(&format!("\n\n{}", html::escape(&stream.title)));
+
+        if !stream.game_name.is_empty() {
+            text.push_str(&format!("\n🎮 {}", html::escape(&stream.game_name)));
+        }
+
+        text.push('\n');
+        text.push_str(&Text::Viewers(stream.viewer_count).localize(language));
+    }
+
+    text
+}
+
+pub fn template_values(
+    broadcaster_login: &str,
+    broadcaster_name: &str,
+    stream: Option<&Stream>,
+) -> TemplateValues {
+    TemplateValues {
+        display_name: broadcaster_name.to_string(),
+        login: broadcaster_login.to_string(),
+        title: stream.map(|v| v.title.clone()).unwrap_or_default(),
+        category: stream.map(|v| v.game_name.clone()).unwrap_or_default(),
+        viewers: stream
+            .map(|v| v.viewer_count.to_string())
+            .unwrap_or_default(),
+        url: channel_url(broadcaster_login).to_string(),
+    }
+}
+
+pub fn thumbnail_url(stream: &Stream) -> Option<url::Url> {
+    let url = stream
+        .thumbnail_url
+        .replace("{width}", "1280")
+        .replace("{height}", "720");
+
+    // Telegram caches photos by url, the timestamp keeps thumbnails fresh
+    url::Url::parse(&format!("{}?t={}", url, Utc::now().timestamp())).ok()
+}
+
+fn thread_id(message_thread_id: Option<i32>) -> Option<ThreadId> {
+    message_thread_id.map(|id| ThreadId(MessageId(id)))
+}
+
+pub fn channel_url(broadcaster_login: &str) -> url::Url {
+    url::Url::parse(&format!("https://twitch.tv/{}", broadcaster_login)).unwrap()
+}
+
+fn watch_keyboard(language: Language, broadcaster_login: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+        Text::WatchOnTwitch.localize(language),
+        channel_url(broadcaster_login),
+    )]])
+}
+
+pub fn format_duration(language: Language, duration: chrono::TimeDelta) -> String {
+    let minutes = duration.num_minutes().max(0);
+
+    Text::Duration {
+        hours: minutes / 60,
+        minutes: minutes % 60,
+    }
+    .localize(language)
+}
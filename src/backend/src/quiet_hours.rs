@@ -0,0 +1,147 @@
+use std::fmt;
+
+use chrono::NaiveTime;
+
+const TIME_FORMAT: &str = "%H:%M";
+
+/// A daily period, in the local time of the chat, during which notifications
+/// shouldn't disturb. `from` is after `to` when the period spans midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuietHours {
+    pub from: NaiveTime,
+    pub to: NaiveTime,
+}
+
+impl QuietHours {
+    /// Parses a period such as `23:00-08:00`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (from, to) = value.split_once('-')?;
+
+        let from = NaiveTime::parse_from_str(from.trim(), TIME_FORMAT).ok()?;
+        let to = NaiveTime::parse_from_str(to.trim(), TIME_FORMAT).ok()?;
+
+        if from == to {
+            return None;
+        }
+
+        Some(Self { from, to })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from < self.to {
+            self.from <= time && time < self.to
+        } else {
+            self.from <= time || time < self.to
+        }
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.from.format(TIME_FORMAT),
+            self.to.format(TIME_FORMAT)
+        )
+    }
+}
+
+/// What happens to live notifications during quiet hours.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuietMode {
+    /// Sent as usual, but without a sound
+    #[default]
+    Silent,
+    /// Held back and sent as one message once quiet hours are over
+    Digest,
+}
+
+impl QuietMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "silent" => Some(Self::Silent),
+            "digest" => Some(Self::Digest),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Silent => "silent",
+            Self::Digest => "digest",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parses_periods() {
+        assert_eq!(
+            QuietHours::parse(" 23:00 - 08:30 "),
+            Some(QuietHours {
+                from: time(23, 0),
+                to: time(8, 30),
+            })
+        );
+        assert_eq!(
+            QuietHours::parse("09:00-17:00").unwrap().to_string(),
+            "09:00-17:00"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_periods() {
+        assert_eq!(QuietHours::parse("23:00"), None);
+        assert_eq!(QuietHours::parse("25:00-08:00"), None);
+        assert_eq!(QuietHours::parse("23:00-8"), None);
+        assert_eq!(QuietHours::parse("night"), None);
+    }
+
+    #[test]
+    fn rejects_equal_bounds() {
+        assert_eq!(QuietHours::parse("08:00-08:00"), None);
+    }
+
+    #[test]
+    fn contains_within_a_day() {
+        let quiet_hours = QuietHours::parse("09:00-17:00").unwrap();
+
+        assert!(quiet_hours.contains(time(9, 0)));
+        assert!(quiet_hours.contains(time(12, 0)));
+        assert!(!quiet_hours.contains(time(17, 0)));
+        assert!(!quiet_hours.contains(time(8, 59)));
+        assert!(!quiet_hours.contains(time(23, 0)));
+    }
+
+    #[test]
+    fn contains_across_midnight() {
+        let quiet_hours = QuietHours::parse("23:00-08:00").unwrap();
+
+        assert!(quiet_hours.contains(time(23, 0)));
+        assert!(quiet_hours.contains(time(0, 0)));
+        assert!(quiet_hours.contains(time(7, 59)));
+        assert!(!quiet_hours.contains(time(8, 0)));
+        assert!(!quiet_hours.contains(time(12, 0)));
+        assert!(!quiet_hours.contains(time(22, 59)));
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(QuietMode::parse("silent"), Some(QuietMode::Silent));
+        assert_eq!(QuietMode::parse("Digest"), Some(QuietMode::Digest));
+        assert_eq!(QuietMode::parse("loud"), None);
+        assert_eq!(QuietMode::parse(""), None);
+
+        for mode in [QuietMode::Silent, QuietMode::Digest] {
+            assert_eq!(QuietMode::parse(mode.as_str()), Some(mode));
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use mongodb::{
+    Client, Collection,
+    bson::{DateTime, Document, doc},
+};
+
+use crate::config::CONFIG;
+
+/// Keeps a trail of changes made to chats without anyone asking, like
+/// removing the subscriptions of a chat the bot was kicked from.
+pub struct AuditRepository {}
+
+impl AuditRepository {
+    async fn get_collection() -> mongodb::error::Result<Collection<Document>> {
+        let client = Client::with_uri_str(CONFIG.mongodb_connection_string.clone()).await?;
+
+        let database = client.database("telegram-twitch-notifier");
+
+        Ok(database.collection("audit"))
+    }
+
+    pub async fn record(chat_id: i64, event: &str, details: String) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .insert_one(doc! {
+                "chat_id": chat_id,
+                "event": event,
+                "details": details,
+                "created_at": DateTime::now(),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::StreamExt as _;
+use mongodb::{
+    Client, Collection,
+    bson::{self, Document, doc},
+};
+
+use crate::{
+    config::CONFIG,
+    i18n::Language,
+    quiet_hours::{QuietHours, QuietMode},
+};
+
+pub struct ChatRepository {}
+
+/// Per chat preferences. A chat without a document uses the defaults.
+pub struct ChatSettings {
+    pub chat_id: i64,
+    /// Chosen with /language
+    pub language: Option<String>,
+    /// Telegram `language_code` of the user, remembered for notifications
+    pub language_code: Option<String>,
+    /// IANA name such as `Europe/Moscow`, quiet hours are in UTC without it
+    pub timezone: Option<String>,
+    pub quiet_hours: Option<QuietHours>,
+    pub quiet_mode: QuietMode,
+    /// Cleared once the bot can no longer post to the chat
+    pub active: bool,
+}
+
+impl From<Document> for ChatSettings {
+    fn from(doc: Document) -> Self {
+        Self {
+            chat_id: doc.get_i64("chat_id").unwrap(),
+            language: doc.get_str("language").ok().map(|v| v.to_string()),
+            language_code: doc.get_str("language_code").ok().map(|v| v.to_string()),
+            timezone: doc.get_str("timezone").ok().map(|v| v.to_string()),
+            quiet_hours: doc.get_str("quiet_hours").ok().and_then(QuietHours::parse),
+            quiet_mode: doc
+                .get_str("quiet_mode")
+                .ok()
+                .and_then(QuietMode::parse)
+                .unwrap_or_default(),
+            active: doc.get_bool("active").unwrap_or(true),
+        }
+    }
+}
+
+/// A live notification held back during quiet hours.
+pub struct DigestEntry {
+    pub broadcaster_id: String,
+    pub broadcaster_login: String,
+    pub broadcaster_display_name: String,
+    pub title: Option<String>,
+    pub message_thread_id: Option<i32>,
+    /// The stream ended before the digest went out
+    pub ended: bool,
+}
+
+impl From<&Document> for DigestEntry {
+    fn from(doc: &Document) -> Self {
+        Self {
+            broadcaster_id: doc
+                .get_str("broadcaster_id")
+                .unwrap_or_default()
+                .to_string(),
+            broadcaster_login: doc.get_str("broadcaster_login").unwrap().to_string(),
+            broadcaster_display_name: doc.get_str("broadcaster_display_name").unwrap().to_string(),
+            title: doc.get_str("title").ok().map(|v| v.to_string()),
+            message_thread_id: doc.get_i32("message_thread_id").ok(),
+            ended: doc.get_bool("ended").unwrap_or(false),
+        }
+    }
+}
+
+impl From<&DigestEntry> for Document {
+    fn from(entry: &DigestEntry) -> Self {
+        doc! {
+            "broadcaster_id": entry.broadcaster_id.clone(),
+            "broadcaster_login": entry.broadcaster_login.clone(),
+            "broadcaster_display_name": entry.broadcaster_display_name.clone(),
+            "title": entry.title.clone(),
+            "message_thread_id": entry.message_thread_id,
+            "ended": entry.ended,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn language(&self) -> Language {
+        self.language
+            .as_deref()
+            .or(self.language_code.as_deref())
+            .and_then(Language::from_code)
+            .unwrap_or_default()
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(Tz::UTC)
+    }
+
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        self.quiet_hours.is_some_and(|quiet_hours| {
+            quiet_hours.contains(now.with_timezone(&self.timezone()).time())
+        })
+    }
+}
+
+impl ChatRepository {
+    async fn get_collection() -> mongodb::error::Result<Collection<Document>> {
+        let client = Client::with_uri_str(CONFIG.mongodb_connection_string.clone()).await?;
+
+        let database = client.database("telegram-twitch-notifier");
+
+        Ok(database.collection("chats"))
+    }
+
+    pub async fn get(chat_id: i64) -> mongodb::error::Result<Option<ChatSettings>> {
+        let collection = Self::get_collection().await?;
+
+        let doc = collection.find_one(doc! { "chat_id": chat_id }).await?;
+
+        Ok(doc.map(ChatSettings::from))
+    }
+
+    pub async fn get_many(chat_ids: &[i64]) -> mongodb::error::Result<Vec<ChatSettings>> {
+        let collection = Self::get_collection().await?;
+
+        let mut chats = collection
+            .find(doc! { "chat_id": { "$in": chat_ids } })
+            .await?;
+
+        let mut result = Vec::new();
+
+        while let Some(chat) = chats.next().await {
+            result.push(ChatSettings::from(chat?));
+        }
+
+        Ok(result)
+    }
+
+    pub async fn active_chat_ids() -> mongodb::error::Result<Vec<i64>> {
+        let collection = Self::get_collection().await?;
+
+        let ids = collection
+            .distinct("chat_id", doc! { "active": { "$ne": false } })
+            .await?;
+
+        Ok(ids.into_iter().filter_map(|id| id.as_i64()).collect())
+    }
+
+    pub async fn inactive_chat_ids() -> mongodb::error::Result<Vec<i64>> {
+        let collection = Self::get_collection().await?;
+
+        let ids = collection
+            .distinct("chat_id", doc! { "active": false })
+            .await?;
+
+        Ok(ids.into_iter().filter_map(|id| id.as_i64()).collect())
+    }
+
+    pub async fn set_language(
+        chat_id: i64,
+        language: Option<String>,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! { "$set": { "language": language } },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn remember_language_code(
+        chat_id: i64,
+        language_code: String,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! { "$set": { "language_code": language_code } },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks the chat as gone and returns whether it was active before.
+    pub async fn deactivate(chat_id: i64, reason: String) -> mongodb::error::Result<bool> {
+        let collection = Self::get_collection().await?;
+
+        let previous = collection
+            .find_one_and_update(
+                doc! { "chat_id": chat_id },
+                doc! {
+                    "$set": {
+                        "active": false,
+                        "deactivation_reason": reason,
+                        "deactivated_at": bson::DateTime::now(),
+                    }
+                },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(previous.is_none_or(|doc| doc.get_bool("active").unwrap_or(true)))
+    }
+
+    pub async fn activate(chat_id: i64) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id, "active": false },
+                doc! {
+                    "$set": { "active": true },
+                    "$unset": { "deactivation_reason": "", "deactivated_at": "" },
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn migrate_chat(chat_id: i64, new_chat_id: i64) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! { "$set": { "chat_id": new_chat_id } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_timezone(chat_id: i64, timezone: String) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! { "$set": { "timezone": timezone } },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_quiet_hours(
+        chat_id: i64,
+        quiet_hours: Option<QuietHours>,
+        quiet_mode: QuietMode,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! {
+                    "$set": {
+                        "quiet_hours": quiet_hours.map(|v| v.to_string()),
+                        "quiet_mode": quiet_mode.as_str(),
+                    }
+                },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn push_digest(chat_id: i64, entry: DigestEntry) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! { "$push": { "digest": Document::from(&entry) } },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks the held back notifications of the broadcaster as ended and
+    /// returns the chats that had one.
+    pub async fn end_digest_entries(broadcaster_id: &str) -> mongodb::error::Result<Vec<i64>> {
+        let collection = Self::get_collection().await?;
+
+        let filter = doc! { "digest.broadcaster_id": broadcaster_id };
+
+        let chat_ids = collection.distinct("chat_id", filter.clone()).await?;
+
+        collection
+            .update_many(filter, doc! { "$set": { "digest.$[entry].ended": true } })
+            .array_filters(vec![doc! { "entry.broadcaster_id": broadcaster_id }])
+            .await?;
+
+        Ok(chat_ids.into_iter().filter_map(|id| id.as_i64()).collect())
+    }
+
+    /// Chats with live notifications held back for a digest.
+    pub async fn all_with_digest() -> mongodb::error::Result<Vec<ChatSettings>> {
+        let collection = Self::get_collection().await?;
+
+        let mut chats = collection
+            .find(doc! { "digest.0": { "$exists": true } })
+            .await?;
+
+        let mut result = Vec::new();
+
+        while let Some(chat) = chats.next().await {
+            result.push(ChatSettings::from(chat?));
+        }
+
+        Ok(result)
+    }
+
+    /// Removes the held back notifications of the chat and returns them.
+    pub async fn take_digest(chat_id: i64) -> mongodb::error::Result<Vec<DigestEntry>> {
+        let collection = Self::get_collection().await?;
+
+        let doc = collection
+            .find_one_and_update(
+                doc! { "chat_id": chat_id },
+                doc! { "$unset": { "digest": "" } },
+            )
+            .await?;
+
+        Ok(doc
+            .as_ref()
+            .and_then(|doc| doc.get_array("digest").ok())
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(|entry| entry.as_document())
+                    .map(DigestEntry::from)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Puts taken notifications back in front of the ones held since, e.g.
+    /// when the digest couldn't be sent.
+    pub async fn restore_digest(
+        chat_id: i64,
+        entries: &[DigestEntry],
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "chat_id": chat_id },
+                doc! {
+                    "$push": {
+                        "digest": {
+                            "$each": entries.iter().map(Document::from).collect::<Vec<_>>(),
+                            "$position": 0,
+                        }
+                    }
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+}
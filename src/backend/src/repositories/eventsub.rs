@@ -0,0 +1,146 @@
+use futures::StreamExt as _;
+use mongodb::{
+    Client, Collection,
+    bson::{DateTime, Document, doc, oid::ObjectId},
+};
+
+use crate::config::CONFIG;
+
+pub struct EventSubRepository {}
+
+pub struct EventSubRegistration {
+    pub id: ObjectId,
+    pub broadcaster_id: String,
+    pub subscription_id: String,
+    pub type_: String,
+    pub version: String,
+    pub status: String,
+    pub cost: u64,
+    pub verified_at: DateTime,
+}
+
+impl From<Document> for EventSubRegistration {
+    fn from(doc: Document) -> Self {
+        Self {
+            id: doc.get_object_id("_id").unwrap(),
+            broadcaster_id: doc.get_str("broadcaster_id").unwrap().to_string(),
+            subscription_id: doc.get_str("subscription_id").unwrap().to_string(),
+            type_: doc.get_str("type").unwrap().to_string(),
+            version: doc.get_str("version").unwrap().to_string(),
+            status: doc.get_str("status").unwrap().to_string(),
+            cost: doc.get_i64("cost").unwrap() as u64,
+            verified_at: *doc.get_datetime("verified_at").unwrap(),
+        }
+    }
+}
+
+impl EventSubRepository {
+    async fn get_collection() -> mongodb::error::Result<Collection<Document>> {
+        let client = Client::with_uri_str(CONFIG.mongodb_connection_string.clone()).await?;
+
+        let database = client.database("telegram-twitch-notifier");
+
+        Ok(database.collection("eventsub"))
+    }
+
+    pub async fn upsert(
+        broadcaster_id: String,
+        subscription_id: String,
+        type_: String,
+        version: String,
+        status: String,
+        cost: u64,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! {
+                    "broadcaster_id": broadcaster_id,
+                    "type": type_,
+                },
+                doc! {
+                    "$set": {
+                        "subscription_id": subscription_id,
+                        "version": version,
+                        "status": status,
+                        "cost": cost as i64,
+                        "verified_at": DateTime::now(),
+                    }
+                },
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn all() -> mongodb::error::Result<Vec<EventSubRegistration>> {
+        let collection = Self::get_collection().await?;
+
+        let mut registrations = collection.find(doc! {}).await?;
+
+        let mut result = Vec::new();
+
+        while let Some(registration) = registrations.next().await {
+            result.push(EventSubRegistration::from(registration?));
+        }
+
+        Ok(result)
+    }
+
+    pub async fn all_by_broadcaster(
+        broadcaster_id: &str,
+    ) -> mongodb::error::Result<Vec<EventSubRegistration>> {
+        let collection = Self::get_collection().await?;
+
+        let mut registrations = collection
+            .find(doc! { "broadcaster_id": broadcaster_id })
+            .await?;
+
+        let mut result = Vec::new();
+
+        while let Some(registration) = registrations.next().await {
+            result.push(EventSubRegistration::from(registration?));
+        }
+
+        Ok(result)
+    }
+
+    pub async fn count_by_status(status: &str) -> mongodb::error::Result<u64> {
+        let collection = Self::get_collection().await?;
+
+        collection.count_documents(doc! { "status": status }).await
+    }
+
+    pub async fn set_status(subscription_id: String, status: String) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "subscription_id": subscription_id },
+                doc! { "$set": { "status": status } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_all() -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection.delete_many(doc! {}).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_by_subscription_id(subscription_id: String) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .delete_one(doc! { "subscription_id": subscription_id })
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1 +1,5 @@
+pub mod audit;
+pub mod chats;
+pub mod eventsub;
+pub mod stats;
 pub mod subscriptions;
@@ -0,0 +1,48 @@
+use chrono::NaiveDate;
+use mongodb::{
+    Client, Collection,
+    bson::{Document, doc},
+};
+
+use crate::config::CONFIG;
+
+/// Chats removed because the bot can't post to them anymore.
+pub const REMOVED_CHATS: &str = "removed_chats";
+
+/// Messages the notifier delivered on the day, in UTC.
+pub fn notifications_sent(date: NaiveDate) -> String {
+    format!("notifications_sent:{}", date)
+}
+
+pub struct StatsRepository {}
+
+impl StatsRepository {
+    async fn get_collection() -> mongodb::error::Result<Collection<Document>> {
+        let client = Client::with_uri_str(CONFIG.mongodb_connection_string.clone()).await?;
+
+        let database = client.database("telegram-twitch-notifier");
+
+        Ok(database.collection("stats"))
+    }
+
+    pub async fn increment(counter: &str) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(doc! { "_id": counter }, doc! { "$inc": { "count": 1_i64 } })
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(counter: &str) -> mongodb::error::Result<i64> {
+        let collection = Self::get_collection().await?;
+
+        let doc = collection.find_one(doc! { "_id": counter }).await?;
+
+        Ok(doc
+            .and_then(|doc| doc.get_i64("count").ok())
+            .unwrap_or_default())
+    }
+}
@@ -5,18 +5,76 @@ use mongodb::{
 };
 use serde::Serialize;
 
-use crate::config::CONFIG;
+use crate::{config::CONFIG, twitch_client::Broadcaster};
 
 pub struct SubscriptionRepository {}
 
+/// Where a subscription was made, to see which ways of subscribing are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionSource {
+    Command,
+    Button,
+    WebApp,
+    DeepLink,
+}
+
+impl SubscriptionSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Button => "button",
+            Self::WebApp => "web_app",
+            Self::DeepLink => "deeplink",
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct Subscription {
+    pub id: ObjectId,
+    pub broadcaster_id: String,
+    pub broadcaster_login: String,
+    pub broadcaster_display_name: String,
+    /// Private, group, supergroup or channel chat the notifications go to
+    pub chat_id: i64,
+    /// Forum topic of the chat, if subscribed from one
+    pub message_thread_id: Option<i32>,
+    pub notify_channel_updates: bool,
+    /// Custom live notification, see `templates`
+    pub template: Option<String>,
+    /// Favorites are notified about even during quiet hours
+    pub favorite: bool,
+    /// See `SubscriptionSource`, missing for subscriptions made before
+    pub source: Option<String>,
+    pub active: bool,
+}
+
+impl From<Document> for Subscription {
+    fn from(doc: Document) -> Self {
+        Self {
+            id: doc.get_object_id("_id").unwrap(),
+            broadcaster_id: doc.get_str("broadcaster_id").unwrap().to_string(),
+            broadcaster_login: doc.get_str("broadcaster_login").unwrap().to_string(),
+            broadcaster_display_name: doc.get_str("broadcaster_display_name").unwrap().to_string(),
+            chat_id: doc.get_i64("chat_id").unwrap(),
+            message_thread_id: doc.get_i32("message_thread_id").ok(),
+            notify_channel_updates: doc.get_bool("notify_channel_updates").unwrap_or(false),
+            template: doc.get_str("template").ok().map(|v| v.to_string()),
+            favorite: doc.get_bool("favorite").unwrap_or(false),
+            source: doc.get_str("source").ok().map(|v| v.to_string()),
+            active: doc.get_bool("active").unwrap_or(true),
+        }
+    }
+}
+
+/// A document written before subscriptions were keyed by broadcaster id.
+pub struct LegacySubscription {
     pub id: ObjectId,
     pub streamer: String,
     pub telegram_user_id: u64,
 }
 
-impl From<Document> for Subscription {
+impl From<Document> for LegacySubscription {
     fn from(doc: Document) -> Self {
         Self {
             id: doc.get_object_id("_id").unwrap(),
@@ -46,27 +104,70 @@ impl SubscriptionRepository {
         }
     }
 
-    pub async fn get_or_create(
-        streamer: String,
-        telegram_user_id: u64,
-    ) -> mongodb::error::Result<Subscription> {
+    pub async fn get_by_login(
+        broadcaster_login: String,
+        chat_id: i64,
+    ) -> mongodb::error::Result<Option<Subscription>> {
         let collection = Self::get_collection().await?;
 
-        let existing = collection
+        let doc = collection
             .find_one(doc! {
-                "streamer": streamer.clone(),
-                "telegram_user_id": telegram_user_id as i64,
+                "broadcaster_login": broadcaster_login,
+                "chat_id": chat_id,
             })
             .await?;
 
+        match doc {
+            Some(doc) => Ok(Some(Subscription::from(doc))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_or_create(
+        broadcaster: Broadcaster,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+        source: SubscriptionSource,
+    ) -> mongodb::error::Result<Subscription> {
+        let collection = Self::get_collection().await?;
+
+        let filter = doc! {
+            "broadcaster_id": broadcaster.id.clone(),
+            "chat_id": chat_id,
+        };
+
+        let existing = collection.find_one(filter.clone()).await?;
+
         if let Some(v) = existing {
-            return Ok(Subscription::from(v));
+            // Keep login and display name fresh after channel renames
+            collection
+                .update_one(
+                    filter,
+                    doc! {
+                        "$set": {
+                            "broadcaster_login": broadcaster.login,
+                            "broadcaster_display_name": broadcaster.display_name,
+                            "message_thread_id": message_thread_id,
+                            "active": true,
+                        }
+                    },
+                )
+                .await?;
+
+            let id = v.get_object_id("_id").unwrap();
+
+            return Ok(SubscriptionRepository::get_by_id(id).await?.unwrap());
         }
 
         let created = collection
             .insert_one(doc! {
-                "streamer": streamer,
-                "telegram_user_id": telegram_user_id as i64,
+                "broadcaster_id": broadcaster.id,
+                "broadcaster_login": broadcaster.login,
+                "broadcaster_display_name": broadcaster.display_name,
+                "chat_id": chat_id,
+                "message_thread_id": message_thread_id,
+                "source": source.as_str(),
+                "active": true,
             })
             .await?;
 
@@ -77,24 +178,108 @@ impl SubscriptionRepository {
             .unwrap())
     }
 
-    pub async fn delete(streamer: String, telegram_user_id: u64) -> mongodb::error::Result<()> {
+    pub async fn delete(broadcaster_id: String, chat_id: i64) -> mongodb::error::Result<()> {
         let collection = Self::get_collection().await?;
 
         collection
             .delete_one(doc! {
-                "streamer": streamer,
-                "telegram_user_id": telegram_user_id as i64,
+                "broadcaster_id": broadcaster_id,
+                "chat_id": chat_id,
             })
             .await?;
 
         Ok(())
     }
 
-    pub async fn all_by_user(telegram_user_id: u64) -> mongodb::error::Result<Vec<Subscription>> {
+    pub async fn delete_by_chat(chat_id: i64) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection.delete_many(doc! { "chat_id": chat_id }).await?;
+
+        Ok(())
+    }
+
+    /// Moves the subscriptions of a group that became a supergroup.
+    pub async fn migrate_chat(chat_id: i64, new_chat_id: i64) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_many(
+                doc! { "chat_id": chat_id },
+                doc! { "$set": { "chat_id": new_chat_id } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_notify_channel_updates(
+        id: ObjectId,
+        notify_channel_updates: bool,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "notify_channel_updates": notify_channel_updates } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_template(
+        id: ObjectId,
+        template: Option<String>,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "template": template } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_favorite(id: ObjectId, favorite: bool) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "favorite": favorite } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps the subscriptions of a channel Twitch no longer has, so
+    /// subscribing again after an unban brings them back.
+    pub async fn deactivate_by_broadcaster(broadcaster_id: &str) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .update_many(
+                doc! { "broadcaster_id": broadcaster_id },
+                doc! { "$set": { "active": false } },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn all_by_broadcaster(
+        broadcaster_id: &str,
+    ) -> mongodb::error::Result<Vec<Subscription>> {
         let collection = Self::get_collection().await?;
 
         let mut subs = collection
-            .find(doc! { "telegram_user_id": telegram_user_id as i64 })
+            .find(doc! { "broadcaster_id": broadcaster_id })
             .await?;
 
         let mut result = Vec::new();
@@ -106,6 +291,20 @@ impl SubscriptionRepository {
         Ok(result)
     }
 
+    pub async fn all_by_chat(chat_id: i64) -> mongodb::error::Result<Vec<Subscription>> {
+        let collection = Self::get_collection().await?;
+
+        let mut subs = collection.find(doc! { "chat_id": chat_id }).await?;
+
+        let mut result = Vec::new();
+
+        while let Some(sub) = subs.next().await {
+            result.push(Subscription::from(sub?));
+        }
+
+        Ok(result)
+    }
+
     pub async fn all() -> mongodb::error::Result<Vec<Subscription>> {
         let collection = Self::get_collection().await?;
 
@@ -119,4 +318,107 @@ impl SubscriptionRepository {
 
         Ok(result)
     }
+
+    pub async fn count_active() -> mongodb::error::Result<u64> {
+        let collection = Self::get_collection().await?;
+
+        collection
+            .count_documents(doc! { "active": { "$ne": false } })
+            .await
+    }
+
+    pub async fn distinct_broadcaster_ids() -> mongodb::error::Result<Vec<String>> {
+        let collection = Self::get_collection().await?;
+
+        let ids = collection
+            .distinct("broadcaster_id", doc! { "active": { "$ne": false } })
+            .await?;
+
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| id.as_str().map(|v| v.to_string()))
+            .collect())
+    }
+
+    pub async fn distinct_chat_ids() -> mongodb::error::Result<Vec<i64>> {
+        let collection = Self::get_collection().await?;
+
+        let ids = collection.distinct("chat_id", doc! {}).await?;
+
+        Ok(ids.into_iter().filter_map(|id| id.as_i64()).collect())
+    }
+
+    pub async fn all_legacy() -> mongodb::error::Result<Vec<LegacySubscription>> {
+        let collection = Self::get_collection().await?;
+
+        let mut subs = collection
+            .find(doc! { "broadcaster_id": { "$exists": false } })
+            .await?;
+
+        let mut result = Vec::new();
+
+        while let Some(sub) = subs.next().await {
+            result.push(LegacySubscription::from(sub?));
+        }
+
+        Ok(result)
+    }
+
+    pub async fn migrate_legacy(
+        legacy: LegacySubscription,
+        broadcaster: Option<Broadcaster>,
+    ) -> mongodb::error::Result<()> {
+        let collection = Self::get_collection().await?;
+
+        let broadcaster = match broadcaster {
+            Some(v) => v,
+            None => {
+                collection.delete_one(doc! { "_id": legacy.id }).await?;
+                return Ok(());
+            }
+        };
+
+        let duplicate = collection
+            .find_one(doc! {
+                "broadcaster_id": broadcaster.id.clone(),
+                "telegram_user_id": legacy.telegram_user_id as i64,
+            })
+            .await?;
+
+        if duplicate.is_some() {
+            collection.delete_one(doc! { "_id": legacy.id }).await?;
+            return Ok(());
+        }
+
+        collection
+            .update_one(
+                doc! { "_id": legacy.id },
+                doc! {
+                    "$set": {
+                        "broadcaster_id": broadcaster.id,
+                        "broadcaster_login": broadcaster.login,
+                        "broadcaster_display_name": broadcaster.display_name,
+                    },
+                    "$unset": { "streamer": "" },
+                },
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Moves subscriptions made before group chats were supported onto the
+    /// chat id. Private chats share their id with the user.
+    pub async fn migrate_chat_ids() -> mongodb::error::Result<u64> {
+        let collection = Self::get_collection().await?;
+
+        let result = collection
+            .update_many(
+                doc! { "chat_id": { "$exists": false } },
+                doc! { "$rename": { "telegram_user_id": "chat_id" } },
+            )
+            .await?;
+
+        Ok(result.modified_count)
+    }
 }
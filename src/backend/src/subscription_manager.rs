@@ -1,11 +1,21 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use tokio::sync::RwLock;
 
-use crate::repositories::subscriptions::SubscriptionRepository;
+use crate::{
+    repositories::{
+        audit::AuditRepository,
+        chats::ChatRepository,
+        stats::{REMOVED_CHATS, StatsRepository},
+        subscriptions::{SubscriptionRepository, SubscriptionSource},
+    },
+    twitch_client::Broadcaster,
+};
 
 pub struct SubscriptionManager {
-    pub subscriptions: RwLock<HashMap<String, HashSet<u64>>>,
+    /// Telegram chat ids, with the forum topic to post in, keyed by Twitch
+    /// broadcaster id
+    pub subscriptions: RwLock<HashMap<String, HashMap<i64, Option<i32>>>>,
 }
 
 impl SubscriptionManager {
@@ -18,43 +28,128 @@ impl SubscriptionManager {
     pub async fn load(&self) -> mongodb::error::Result<()> {
         let subs = SubscriptionRepository::all().await?;
 
-        for sub in subs {
+        for sub in subs.into_iter().filter(|sub| sub.active) {
             self.subscriptions
                 .write()
                 .await
-                .entry(sub.streamer.clone())
-                .or_insert(HashSet::new())
-                .insert(sub.telegram_user_id);
+                .entry(sub.broadcaster_id.clone())
+                .or_insert(HashMap::new())
+                .insert(sub.chat_id, sub.message_thread_id);
         }
 
         Ok(())
     }
 
-    pub async fn subscribe(&self, telegram_user_id: u64, username: String) {
-        tracing::debug!("Subscribing {} to {}", telegram_user_id, username);
+    pub async fn subscribe(
+        &self,
+        chat_id: i64,
+        message_thread_id: Option<i32>,
+        broadcaster: Broadcaster,
+        source: SubscriptionSource,
+    ) {
+        tracing::debug!("Subscribing {} to {}", chat_id, broadcaster.login);
 
-        let inserted = self
-            .subscriptions
+        self.subscriptions
             .write()
             .await
-            .entry(username.clone())
-            .or_insert(HashSet::new())
-            .insert(telegram_user_id);
+            .entry(broadcaster.id.clone())
+            .or_insert(HashMap::new())
+            .insert(chat_id, message_thread_id);
 
-        if !inserted {
-            return;
-        }
-
-        SubscriptionRepository::get_or_create(username, telegram_user_id)
+        SubscriptionRepository::get_or_create(broadcaster, chat_id, message_thread_id, source)
             .await
             .expect("Failed to create subscription");
+
+        // A chat that blocked the bot before may be back
+        ChatRepository::activate(chat_id)
+            .await
+            .expect("Failed to activate chat");
     }
 
-    pub async fn unsubscribe(&self, telegram_user_id: u64, username: String) {
-        tracing::debug!("Unsubscribing {} from {}", telegram_user_id, username);
+    pub async fn unsubscribe(&self, chat_id: i64, broadcaster_id: String) {
+        tracing::debug!("Unsubscribing {} from {}", chat_id, broadcaster_id);
+
+        {
+            let mut subscriptions = self.subscriptions.write().await;
 
-        SubscriptionRepository::delete(username, telegram_user_id)
+            if let Some(chat_ids) = subscriptions.get_mut(&broadcaster_id) {
+                chat_ids.remove(&chat_id);
+
+                // The Twitch side is released once nobody follows the streamer
+                if chat_ids.is_empty() {
+                    subscriptions.remove(&broadcaster_id);
+                }
+            }
+        }
+
+        SubscriptionRepository::delete(broadcaster_id, chat_id)
             .await
             .expect("Failed to delete subscription");
     }
+
+    /// Drops every subscription of a chat the bot can't post to anymore and
+    /// marks the chat inactive.
+    pub async fn remove_chat(&self, chat_id: i64, reason: String) {
+        tracing::debug!("Removing chat {}: {}", chat_id, reason);
+
+        {
+            let mut subscriptions = self.subscriptions.write().await;
+
+            for chat_ids in subscriptions.values_mut() {
+                chat_ids.remove(&chat_id);
+            }
+
+            subscriptions.retain(|_, chat_ids| !chat_ids.is_empty());
+        }
+
+        SubscriptionRepository::delete_by_chat(chat_id)
+            .await
+            .expect("Failed to delete subscriptions");
+
+        let was_active = ChatRepository::deactivate(chat_id, reason.clone())
+            .await
+            .expect("Failed to deactivate chat");
+
+        // Several notifications may fail for the same chat at once
+        if was_active {
+            StatsRepository::increment(REMOVED_CHATS)
+                .await
+                .expect("Failed to count removed chat");
+
+            AuditRepository::record(chat_id, "chat_removed", reason)
+                .await
+                .expect("Failed to record chat removal");
+        }
+    }
+
+    /// Moves everything of a group over to the supergroup it was upgraded to.
+    pub async fn migrate_chat(&self, chat_id: i64, new_chat_id: i64) {
+        tracing::debug!("Migrating chat {} to {}", chat_id, new_chat_id);
+
+        {
+            let mut subscriptions = self.subscriptions.write().await;
+
+            for chat_ids in subscriptions.values_mut() {
+                if let Some(message_thread_id) = chat_ids.remove(&chat_id) {
+                    chat_ids.insert(new_chat_id, message_thread_id);
+                }
+            }
+        }
+
+        SubscriptionRepository::migrate_chat(chat_id, new_chat_id)
+            .await
+            .expect("Failed to migrate subscriptions");
+
+        ChatRepository::migrate_chat(chat_id, new_chat_id)
+            .await
+            .expect("Failed to migrate chat");
+
+        AuditRepository::record(
+            chat_id,
+            "chat_migrated",
+            format!("Migrated to {}", new_chat_id),
+        )
+        .await
+        .expect("Failed to record chat migration");
+    }
 }
@@ -1,20 +1,93 @@
-use std::{error::Error, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    sync::Arc,
+    time::Duration,
+};
 
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use teloxide::{
-    Bot as OriginBot,
+    Bot as OriginBot, RequestError,
     adaptors::{CacheMe, Throttle, throttle::Limits},
     dispatching::{HandlerExt, UpdateFilterExt as _, dialogue::GetChatId},
     dptree::{self, Handler},
     macros::BotCommands,
+    payloads::{
+        AnswerCallbackQuerySetters as _, AnswerInlineQuerySetters as _,
+        EditMessageTextSetters as _, SendMessageSetters as _, SendPhotoSetters as _,
+        SetMyCommandsSetters as _,
+    },
     prelude::{Dispatcher, LoggingErrorHandler, Requester, RequesterExt},
-    types::{BotCommand, Message, Update},
+    types::{
+        BotCommand, BotCommandScope, CallbackQuery, Chat, ChatId, ChatMemberUpdated,
+        InlineKeyboardButton, InlineKeyboardMarkup, InlineQuery, InlineQueryResult,
+        InlineQueryResultArticle, InputFile, InputMessageContent, InputMessageContentText,
+        LinkPreviewOptions, Message, ParseMode, Recipient, Update, User, UserId,
+    },
     update_listeners::webhooks,
+    utils::html,
 };
+use twitch_api::helix::{search::Channel, streams::Stream};
 
-use crate::{config::CONFIG, subscription_manager::SubscriptionManager};
+use crate::{
+    config::CONFIG,
+    i18n::{Language, Text},
+    notifier::{
+        channel_url, format_duration, is_chat_unreachable, live_message_text, send_template,
+        template_values, thumbnail_url,
+    },
+    quiet_hours::{QuietHours, QuietMode},
+    repositories::{
+        chats::ChatRepository,
+        eventsub::EventSubRepository,
+        stats::{self, REMOVED_CHATS, StatsRepository},
+        subscriptions::{SubscriptionRepository, SubscriptionSource},
+    },
+    subscription_manager::SubscriptionManager,
+    templates,
+    twitch_client::{Broadcaster, TwitchClient},
+};
 
 pub type Bot = CacheMe<Throttle<OriginBot>>;
 
+/// Callback data of buttons that subscribe to a broadcaster id.
+pub const SUBSCRIBE_CALLBACK_PREFIX: &str = "subscribe:";
+
+/// Callback data of the /list buttons: unsubscribe from a broadcaster id
+/// and turn to a page, respectively.
+const UNSUBSCRIBE_CALLBACK_PREFIX: &str = "unsubscribe:";
+const LIST_CALLBACK_PREFIX: &str = "list:";
+
+/// Callback data of the /language buttons, followed by a language code.
+const LANGUAGE_CALLBACK_PREFIX: &str = "language:";
+
+/// `/start` payload of links that subscribe to a channel, followed by its
+/// login or broadcaster id.
+pub const SUBSCRIBE_DEEP_LINK_PREFIX: &str = "sub_";
+
+/// Callback data of the button on the card a deep link shows, followed by
+/// a broadcaster id.
+const DEEP_LINK_CALLBACK_PREFIX: &str = "deeplink:";
+
+/// Room left in the card caption for the channel description.
+const DEEP_LINK_DESCRIPTION_LENGTH: usize = 700;
+
+/// How many channels are suggested for a login that doesn't exist.
+const SUGGESTIONS_LIMIT: usize = 5;
+
+/// How many channels an inline query answers with.
+const INLINE_RESULTS_LIMIT: usize = 10;
+
+/// How many subscriptions /list shows per page.
+const LIST_PAGE_SIZE: usize = 10;
+
+/// Pause between /broadcast messages, Telegram allows about 30 per second.
+const BROADCAST_DELAY: Duration = Duration::from_millis(50);
+
+/// How many chats /broadcast sends to between progress updates.
+const BROADCAST_PROGRESS_EVERY: usize = 25;
+
 pub type BotHandlerInternal = Result<(), Box<dyn Error + Send + Sync>>;
 type BotHandler = Handler<
     'static,
@@ -27,21 +100,181 @@ type BotHandler = Handler<
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase")]
 enum Command {
-    Start,
+    Start(String),
     Help,
     Subscribe(String),
     Unsubscribe(String),
+    Updates(String),
+    List,
+    Live,
+    Template(String),
+    Preview(String),
+    Language(String),
+    Favorite(String),
+    Quiet(String),
+    Timezone(String),
+}
+
+/// The language to answer in: the one chosen for the chat with /language,
+/// otherwise the one of the user's Telegram app.
+async fn language(chat_id: ChatId, user: Option<&User>) -> Language {
+    let settings = match ChatRepository::get(chat_id.0).await {
+        Ok(v) => v,
+        Err(err) => {
+            tracing::error!("Failed to get chat settings of {}: {:?}", chat_id, err);
+            None
+        }
+    };
+
+    settings
+        .as_ref()
+        .and_then(|settings| settings.language.as_deref())
+        .or(user.and_then(|user| user.language_code.as_deref()))
+        .and_then(Language::from_code)
+        .unwrap_or_else(|| {
+            settings
+                .map(|settings| settings.language())
+                .unwrap_or_default()
+        })
+}
+
+async fn message_language(message: &Message) -> Language {
+    language(message.chat.id, message.from.as_ref()).await
+}
+
+async fn callback_language(query: &CallbackQuery) -> Language {
+    let chat_id = match query.message.as_ref() {
+        Some(message) => message.chat().id,
+        None => query.from.id.into(),
+    };
+
+    language(chat_id, Some(&query.from)).await
+}
+
+/// Notifications have no user to take the language from, so the language of
+/// whoever subscribes the chat is kept for them.
+async fn remember_language_code(chat_id: ChatId, user: Option<&User>) {
+    let language_code = match user.and_then(|user| user.language_code.clone()) {
+        Some(v) => v,
+        None => return,
+    };
+
+    if let Err(err) = ChatRepository::remember_language_code(chat_id.0, language_code).await {
+        tracing::error!("Failed to remember the language of {}: {:?}", chat_id, err);
+    }
+}
+
+/// Commands of the users listed in `ADMIN_USER_IDS`.
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase")]
+enum AdminCommand {
+    Stats,
+    Broadcast(String),
+    Whois(String),
+}
+
+fn is_admin(message: &Message) -> bool {
+    message
+        .from
+        .as_ref()
+        .is_some_and(|user| CONFIG.admin_user_ids.contains(&user.id.0))
 }
 
 pub async fn help_message_handler(bot: Bot, message: Message) -> BotHandlerInternal {
-    const HELP_MESSAGE: &str = r#"
-Welcome!
+    let language = message_language(&message).await;
+
+    match bot
+        .send_message(message.chat_id().unwrap(), Text::Help.localize(language))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// `/start sub_<login>` from a deep link: shows a card of the channel with a
+/// button to subscribe, anything else gets the help.
+pub async fn start_handler(
+    bot: Bot,
+    message: Message,
+    twitch_client: Arc<TwitchClient>,
+    payload: String,
+) -> BotHandlerInternal {
+    let login_or_id = match payload.trim().strip_prefix(SUBSCRIBE_DEEP_LINK_PREFIX) {
+        Some(v) if is_valid_login(v) => v.to_lowercase(),
+        _ => return help_message_handler(bot, message).await,
+    };
+
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let profile = match twitch_client.get_broadcaster_profile(&login_or_id).await? {
+        Some(v) => v,
+        None => {
+            return match bot
+                .send_message(
+                    chat_id,
+                    Text::LoginNotFound(&login_or_id).localize(language),
+                )
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
 
-This bot allow you to subscribe to receive start stream notifications.
-    "#;
+    let display_name = html::escape(&profile.broadcaster.display_name);
+
+    let mut text = format!("<b>{}</b>", display_name);
+
+    let description = profile.description.trim();
+
+    if !description.is_empty() {
+        let mut description = description
+            .chars()
+            .take(DEEP_LINK_DESCRIPTION_LENGTH)
+            .collect::<String>();
+
+        if profile.description.trim().chars().count() > DEEP_LINK_DESCRIPTION_LENGTH {
+            description.push('…');
+        }
+
+        text.push_str(&format!("\n\n{}", html::escape(&description)));
+    }
+
+    text.push_str(&format!(
+        "\n\n{}",
+        Text::SubscribeConfirm(&display_name).localize(language)
+    ));
+
+    let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        Text::Subscribe.localize(language),
+        format!("{}{}", DEEP_LINK_CALLBACK_PREFIX, profile.broadcaster.id),
+    )]]);
+
+    if let Some(photo_url) = profile
+        .profile_image_url
+        .as_deref()
+        .and_then(|v| url::Url::parse(v).ok())
+    {
+        let result = bot
+            .send_photo(chat_id, InputFile::url(photo_url))
+            .caption(text.clone())
+            .parse_mode(ParseMode::Html)
+            .reply_markup(keyboard.clone())
+            .await;
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(err) => tracing::warn!("Failed to send photo to {}: {:?}", chat_id, err),
+        }
+    }
 
     match bot
-        .send_message(message.chat_id().unwrap(), HELP_MESSAGE)
+        .send_message(chat_id, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(keyboard)
         .await
     {
         Ok(_) => Ok(()),
@@ -49,18 +282,241 @@ This bot allow you to subscribe to receive start stream notifications.
     }
 }
 
+/// Turns `/subscribe` arguments into logins. Accepts plain logins as well as
+/// channel links, separated by spaces or commas.
+fn parse_logins(args: &str) -> Vec<String> {
+    let mut logins = Vec::new();
+
+    for arg in args.split(|c: char| c.is_whitespace() || c == ',') {
+        let arg = arg
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_start_matches("www.")
+            .trim_start_matches("m.");
+        let arg = arg.strip_prefix("twitch.tv/").unwrap_or(arg);
+
+        let login = arg
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('@')
+            .to_lowercase();
+
+        if !login.is_empty() && !logins.contains(&login) {
+            logins.push(login);
+        }
+    }
+
+    logins
+}
+
+fn is_valid_login(login: &str) -> bool {
+    !login.is_empty()
+        && login.len() <= 25
+        && login.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn suggestions_keyboard(suggestions: &[Broadcaster]) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(suggestions.iter().map(|broadcaster| {
+        vec![InlineKeyboardButton::callback(
+            format!("{} ({})", broadcaster.display_name, broadcaster.login),
+            format!("{}{}", SUBSCRIBE_CALLBACK_PREFIX, broadcaster.id),
+        )]
+    }))
+}
+
+/// Whether the user may change the subscriptions of the chat. In groups and
+/// channels that is up to the administrators.
+async fn is_chat_admin(bot: &Bot, chat: &Chat, user_id: UserId) -> Result<bool, RequestError> {
+    if chat.is_private() {
+        return Ok(true);
+    }
+
+    let member = bot.get_chat_member(chat.id, user_id).await?;
+
+    Ok(member.is_privileged())
+}
+
+async fn can_manage_subscriptions(bot: &Bot, message: &Message) -> Result<bool, RequestError> {
+    // Channel posts and anonymous group admins are sent on behalf of the chat
+    if message
+        .sender_chat
+        .as_ref()
+        .is_some_and(|sender_chat| sender_chat.id == message.chat.id)
+    {
+        return Ok(true);
+    }
+
+    match message.from.as_ref() {
+        Some(user) => is_chat_admin(bot, &message.chat, user.id).await,
+        None => Ok(false),
+    }
+}
+
+/// Tells the sender off unless they may change the subscriptions of the chat.
+async fn ensure_can_manage(
+    bot: &Bot,
+    message: &Message,
+    language: Language,
+) -> Result<bool, RequestError> {
+    if can_manage_subscriptions(bot, message).await? {
+        return Ok(true);
+    }
+
+    bot.send_message(message.chat.id, Text::NotAdmin.localize(language))
+        .await?;
+
+    Ok(false)
+}
+
+/// The forum topic the message was sent in, where notifications should go too.
+fn message_thread_id(message: &Message) -> Option<i32> {
+    message
+        .thread_id
+        .filter(|_| message.is_topic_message)
+        .map(|thread_id| thread_id.0.0)
+}
+
+/// The chat a button was pressed in, if the user may change its subscriptions.
+async fn callback_chat_id(
+    bot: &Bot,
+    query: &CallbackQuery,
+) -> Result<Option<ChatId>, RequestError> {
+    let chat = match query.message.as_ref() {
+        Some(message) => message.chat(),
+        None => return Ok(Some(query.from.id.into())),
+    };
+
+    if is_chat_admin(bot, chat, query.from.id).await? {
+        Ok(Some(chat.id))
+    } else {
+        Ok(None)
+    }
+}
+
 pub async fn subscribe_handler(
     bot: Bot,
     message: Message,
     subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
     username: String,
 ) -> BotHandlerInternal {
-    let user_id = message.clone().from.unwrap().id;
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let logins = parse_logins(&username);
+
+    if logins.is_empty() {
+        return match bot
+            .send_message(chat_id, Text::SubscribeUsage.localize(language))
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Box::new(err)),
+        };
+    }
+
+    let mut subscribed = Vec::new();
+    let mut not_found = Vec::new();
+
+    for login in logins {
+        let broadcaster = if is_valid_login(&login) {
+            twitch_client.get_broadcaster_by_login(&login).await?
+        } else {
+            None
+        };
+
+        match broadcaster {
+            Some(broadcaster) => {
+                subscribed.push(broadcaster.display_name.clone());
+                subscription_manager
+                    .subscribe(
+                        chat_id.0,
+                        message_thread_id(&message),
+                        broadcaster,
+                        SubscriptionSource::Command,
+                    )
+                    .await;
+            }
+            None => not_found.push(login),
+        }
+    }
+
+    if !subscribed.is_empty() {
+        remember_language_code(chat_id, message.from.as_ref()).await;
+
+        bot.send_message(
+            chat_id,
+            Text::SubscribedTo(&subscribed.join(", ")).localize(language),
+        )
+        .await?;
+    }
+
+    for login in not_found {
+        let suggestions = match twitch_client
+            .search_channels(&login, SUGGESTIONS_LIMIT)
+            .await
+        {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!("Failed to search channels for {}: {:?}", login, err);
+                Vec::new()
+            }
+        };
+
+        let result = if suggestions.is_empty() {
+            bot.send_message(chat_id, Text::LoginNotFound(&login).localize(language))
+                .await
+        } else {
+            bot.send_message(
+                chat_id,
+                Text::LoginNotFoundSuggestions(&login).localize(language),
+            )
+            .reply_markup(suggestions_keyboard(&suggestions))
+            .await
+        };
+
+        if let Err(err) = result {
+            return Err(Box::new(err));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn unsubscribe_handler(
+    bot: Bot,
+    message: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+    username: String,
+) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let login = username.trim().to_lowercase();
 
-    subscription_manager.subscribe(user_id.0, username).await;
+    let subscription = SubscriptionRepository::get_by_login(login, chat_id.0).await?;
+
+    let text = match subscription {
+        Some(subscription) => {
+            subscription_manager
+                .unsubscribe(chat_id.0, subscription.broadcaster_id)
+                .await;
+            Text::Unsubscribed
+        }
+        None => Text::NotSubscribed,
+    };
 
     match bot
-        .send_message(message.chat_id().unwrap(), "Subscribed!")
+        .send_message(message.chat_id().unwrap(), text.localize(language))
         .await
     {
         Ok(_) => Ok(()),
@@ -68,18 +524,421 @@ pub async fn subscribe_handler(
     }
 }
 
-pub async fn unsubscribe_handler(
+pub async fn updates_handler(bot: Bot, message: Message, username: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let login = username.trim().to_lowercase();
+
+    let subscription = SubscriptionRepository::get_by_login(login, chat_id.0).await?;
+
+    let text = match subscription {
+        Some(subscription) => {
+            let enabled = !subscription.notify_channel_updates;
+
+            SubscriptionRepository::set_notify_channel_updates(subscription.id, enabled).await?;
+
+            if enabled {
+                Text::UpdatesEnabled
+            } else {
+                Text::UpdatesDisabled
+            }
+        }
+        None => Text::NotSubscribed,
+    };
+
+    match bot
+        .send_message(message.chat_id().unwrap(), text.localize(language))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub async fn favorite_handler(bot: Bot, message: Message, username: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let login = username.trim().to_lowercase();
+
+    let subscription = SubscriptionRepository::get_by_login(login, chat_id.0).await?;
+
+    let text = match subscription {
+        Some(subscription) => {
+            let favorite = !subscription.favorite;
+
+            SubscriptionRepository::set_favorite(subscription.id, favorite).await?;
+
+            if favorite {
+                Text::FavoriteEnabled
+            } else {
+                Text::FavoriteDisabled
+            }
+        }
+        None => Text::NotSubscribed,
+    };
+
+    match bot
+        .send_message(message.chat_id().unwrap(), text.localize(language))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Sets quiet hours from `<from>-<to> [silent|digest]` or turns them `off`.
+pub async fn quiet_handler(bot: Bot, message: Message, args: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let mut args = args.split_whitespace();
+
+    let quiet_hours = match (args.next(), args.next(), args.next()) {
+        (Some("off"), None, _) => Some(None),
+        (Some(hours), mode, None) => QuietHours::parse(hours)
+            .zip(mode.map_or(Some(QuietMode::default()), QuietMode::parse))
+            .map(Some),
+        _ => None,
+    };
+
+    let quiet_hours = match quiet_hours {
+        Some(v) => v,
+        None => {
+            return match bot
+                .send_message(chat_id, Text::QuietUsage.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let text = match quiet_hours {
+        Some((quiet_hours, quiet_mode)) => {
+            ChatRepository::set_quiet_hours(chat_id.0, Some(quiet_hours), quiet_mode).await?;
+
+            let timezone = ChatRepository::get(chat_id.0)
+                .await?
+                .map(|settings| settings.timezone())
+                .unwrap_or(Tz::UTC);
+
+            let hours = quiet_hours.to_string();
+
+            match quiet_mode {
+                QuietMode::Silent => Text::QuietSilent {
+                    hours: &hours,
+                    timezone: timezone.name(),
+                },
+                QuietMode::Digest => Text::QuietDigest {
+                    hours: &hours,
+                    timezone: timezone.name(),
+                },
+            }
+            .localize(language)
+        }
+        None => {
+            ChatRepository::set_quiet_hours(chat_id.0, None, QuietMode::default()).await?;
+
+            Text::QuietOff.localize(language)
+        }
+    };
+
+    match bot.send_message(chat_id, text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub async fn timezone_handler(bot: Bot, message: Message, args: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let name = args.trim();
+
+    if name.is_empty() {
+        let timezone = ChatRepository::get(chat_id.0)
+            .await?
+            .map(|settings| settings.timezone())
+            .unwrap_or(Tz::UTC);
+
+        return match bot
+            .send_message(
+                chat_id,
+                Text::TimezoneUsage(timezone.name()).localize(language),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Box::new(err)),
+        };
+    }
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let text = match name.parse::<Tz>() {
+        Ok(timezone) => {
+            ChatRepository::set_timezone(chat_id.0, timezone.name().to_string()).await?;
+
+            Text::TimezoneSet(timezone.name()).localize(language)
+        }
+        Err(_) => Text::TimezoneInvalid(name).localize(language),
+    };
+
+    match bot.send_message(chat_id, text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub async fn template_handler(bot: Bot, message: Message, args: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    let args = args.trim();
+    let (login, template) = args
+        .split_once(char::is_whitespace)
+        .map(|(login, template)| (login, template.trim()))
+        .unwrap_or((args, ""));
+
+    let login = login.to_lowercase();
+
+    let text = if login.is_empty() {
+        Text::TemplateUsage(
+            &templates::PLACEHOLDERS
+                .iter()
+                .map(|v| format!("{{{}}}", v))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .localize(language)
+    } else {
+        match SubscriptionRepository::get_by_login(login.clone(), chat_id.0).await? {
+            None => Text::NotSubscribed.localize(language),
+            Some(subscription) if template.is_empty() => {
+                SubscriptionRepository::set_template(subscription.id, None).await?;
+
+                Text::TemplateReset.localize(language)
+            }
+            Some(subscription) => match templates::validate(template) {
+                Ok(_) => {
+                    SubscriptionRepository::set_template(
+                        subscription.id,
+                        Some(template.to_string()),
+                    )
+                    .await?;
+
+                    Text::TemplateSaved(&login).localize(language)
+                }
+                Err(err) => Text::TemplateInvalid(&err.to_string()).localize(language),
+            },
+        }
+    };
+
+    match bot.send_message(chat_id, text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Shows the live notification of a subscription, filled with the current
+/// stream or made-up values when the streamer is offline.
+pub async fn preview_handler(
     bot: Bot,
     message: Message,
-    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
     username: String,
 ) -> BotHandlerInternal {
-    let user_id = message.clone().from.unwrap().id;
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let login = username.trim().to_lowercase();
+
+    let subscription = match SubscriptionRepository::get_by_login(login, chat_id.0).await? {
+        Some(v) => v,
+        None => {
+            return match bot
+                .send_message(chat_id, Text::NotSubscribed.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    let stream = twitch_client
+        .get_streams(&[subscription.broadcaster_id.clone()])
+        .await?
+        .into_iter()
+        .next();
 
-    subscription_manager.unsubscribe(user_id.0, username).await;
+    let text = match subscription.template.as_deref() {
+        Some(template) => {
+            let mut values = template_values(
+                &subscription.broadcaster_login,
+                &subscription.broadcaster_display_name,
+                stream.as_ref(),
+            );
+
+            if stream.is_none() {
+                values.title = Text::ExampleTitle.localize(language);
+                values.category = "Just Chatting".to_string();
+                values.viewers = "42".to_string();
+            }
+
+            templates::render(template, &values)?
+        }
+        None => live_message_text(
+            language,
+            &subscription.broadcaster_display_name,
+            stream.as_ref(),
+        ),
+    };
+
+    match send_template(
+        text,
+        |err| Text::TemplateRejected(&html::escape(&err.to_string())).localize(language),
+        |text| {
+            bot.send_message(chat_id, text)
+                .parse_mode(ParseMode::Html)
+                .into_future()
+        },
+    )
+    .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Renders one page of the chat's subscriptions, with live streamers marked.
+async fn list_message(
+    twitch_client: &TwitchClient,
+    chat_id: ChatId,
+    page: usize,
+    language: Language,
+) -> Result<(String, InlineKeyboardMarkup), Box<dyn Error + Send + Sync>> {
+    let mut subscriptions = SubscriptionRepository::all_by_chat(chat_id.0).await?;
+
+    if subscriptions.is_empty() {
+        return Ok((
+            Text::NoSubscriptions.localize(language),
+            InlineKeyboardMarkup::default(),
+        ));
+    }
+
+    subscriptions.sort_by_key(|sub| sub.broadcaster_display_name.to_lowercase());
+
+    let pages = subscriptions.len().div_ceil(LIST_PAGE_SIZE);
+    let page = page.min(pages - 1);
+
+    let subscriptions = subscriptions
+        .into_iter()
+        .skip(page * LIST_PAGE_SIZE)
+        .take(LIST_PAGE_SIZE)
+        .collect::<Vec<_>>();
+
+    let broadcaster_ids = subscriptions
+        .iter()
+        .map(|sub| sub.broadcaster_id.clone())
+        .collect::<Vec<_>>();
+
+    let live = match twitch_client.get_streams(&broadcaster_ids).await {
+        Ok(streams) => streams
+            .into_iter()
+            .map(|stream| stream.user_id.to_string())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            tracing::error!("Failed to get streams: {:?}", err);
+            Vec::new()
+        }
+    };
+
+    let mut rows = subscriptions
+        .iter()
+        .map(|sub| {
+            let name = if live.contains(&sub.broadcaster_id) {
+                format!("🔴 {}", sub.broadcaster_display_name)
+            } else {
+                sub.broadcaster_display_name.clone()
+            };
+
+            vec![
+                InlineKeyboardButton::url(name, channel_url(&sub.broadcaster_login)),
+                InlineKeyboardButton::callback(
+                    "❌",
+                    format!(
+                        "{}{}:{}",
+                        UNSUBSCRIBE_CALLBACK_PREFIX, sub.broadcaster_id, page
+                    ),
+                ),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    if pages > 1 {
+        let mut navigation = Vec::new();
+
+        if page > 0 {
+            navigation.push(InlineKeyboardButton::callback(
+                "◀️",
+                format!("{}{}", LIST_CALLBACK_PREFIX, page - 1),
+            ));
+        }
+
+        if page + 1 < pages {
+            navigation.push(InlineKeyboardButton::callback(
+                "▶️",
+                format!("{}{}", LIST_CALLBACK_PREFIX, page + 1),
+            ));
+        }
+
+        rows.push(navigation);
+    }
+
+    Ok((
+        Text::ListHeader {
+            page: page + 1,
+            pages,
+        }
+        .localize(language),
+        InlineKeyboardMarkup::new(rows),
+    ))
+}
+
+pub async fn list_handler(
+    bot: Bot,
+    message: Message,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let language = message_language(&message).await;
+
+    let (text, keyboard) = list_message(&twitch_client, message.chat.id, 0, language).await?;
 
     match bot
-        .send_message(message.chat_id().unwrap(), "Unsubscribed!")
+        .send_message(message.chat_id().unwrap(), text)
+        .reply_markup(keyboard)
         .await
     {
         Ok(_) => Ok(()),
@@ -87,43 +946,837 @@ pub async fn unsubscribe_handler(
     }
 }
 
-pub async fn get_handler() -> BotHandler {
-    dptree::entry().branch(
-        Update::filter_message()
-            .filter_command::<Command>()
-            .endpoint(|bot, message, command, subscription_manager| async move {
-                match command {
-                    Command::Start | Command::Help => help_message_handler(bot, message).await,
-                    Command::Subscribe(username) => {
-                        subscribe_handler(bot, message, subscription_manager, username).await
-                    }
-                    Command::Unsubscribe(username) => {
-                        unsubscribe_handler(bot, message, subscription_manager, username).await
-                    }
-                }
-            }),
+pub async fn live_handler(
+    bot: Bot,
+    message: Message,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let language = message_language(&message).await;
+
+    let broadcaster_ids = SubscriptionRepository::all_by_chat(message.chat.id.0)
+        .await?
+        .into_iter()
+        .map(|sub| sub.broadcaster_id)
+        .collect::<Vec<_>>();
+
+    let mut streams = twitch_client.get_streams_cached(&broadcaster_ids).await?;
+
+    streams.sort_by(|a, b| b.viewer_count.cmp(&a.viewer_count));
+
+    let text = if streams.is_empty() {
+        Text::NobodyLive.localize(language)
+    } else {
+        streams
+            .iter()
+            .map(|stream| {
+                let uptime = DateTime::parse_from_rfc3339(stream.started_at.as_str())
+                    .map(|started_at| {
+                        format_duration(language, Utc::now() - started_at.with_timezone(&Utc))
+                    })
+                    .unwrap_or_default();
+
+                format!(
+                    "🔴 <a href=\"{}\">{}</a> — {}\n🎮 {} · 👀 {} · ⏱ {}",
+                    channel_url(stream.user_login.as_str()),
+                    html::escape(stream.user_name.as_str()),
+                    html::escape(&stream.title),
+                    html::escape(&stream.game_name),
+                    stream.viewer_count,
+                    uptime
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+
+    match bot
+        .send_message(message.chat_id().unwrap(), text)
+        .parse_mode(ParseMode::Html)
+        .link_preview_options(LinkPreviewOptions {
+            is_disabled: true,
+            url: None,
+            prefer_small_media: false,
+            prefer_large_media: false,
+            show_above_text: false,
+        })
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Re-renders the /list message the callback came from.
+async fn edit_list_message(
+    bot: &Bot,
+    query: &CallbackQuery,
+    twitch_client: &TwitchClient,
+    page: usize,
+) -> BotHandlerInternal {
+    let message = match query.message.as_ref() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let language = callback_language(query).await;
+
+    let (text, keyboard) = list_message(twitch_client, message.chat().id, page, language).await?;
+
+    match bot
+        .edit_message_text(message.chat().id, message.id(), text)
+        .reply_markup(keyboard)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub async fn list_callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let page = query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(LIST_CALLBACK_PREFIX))
+        .and_then(|page| page.parse().ok())
+        .unwrap_or(0);
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    edit_list_message(&bot, &query, &twitch_client, page).await
+}
+
+pub async fn unsubscribe_callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let (broadcaster_id, page) = match query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(UNSUBSCRIBE_CALLBACK_PREFIX))
+        .and_then(|data| data.split_once(':'))
+    {
+        Some((broadcaster_id, page)) => (broadcaster_id.to_string(), page.parse().unwrap_or(0)),
+        None => return Ok(()),
+    };
+
+    let language = callback_language(&query).await;
+
+    let chat_id = match callback_chat_id(&bot, &query).await? {
+        Some(v) => v,
+        None => {
+            return match bot
+                .answer_callback_query(query.id)
+                .text(Text::NotAdmin.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    let subscription = SubscriptionRepository::all_by_chat(chat_id.0)
+        .await?
+        .into_iter()
+        .find(|sub| sub.broadcaster_id == broadcaster_id);
+
+    let text = match subscription {
+        Some(subscription) => {
+            subscription_manager
+                .unsubscribe(chat_id.0, broadcaster_id)
+                .await;
+
+            Text::UnsubscribedFrom(&subscription.broadcaster_display_name).localize(language)
+        }
+        None => Text::NotSubscribed.localize(language),
+    };
+
+    bot.answer_callback_query(query.id.clone())
+        .text(text)
+        .await?;
+
+    edit_list_message(&bot, &query, &twitch_client, page).await
+}
+
+fn has_callback_prefix(query: &CallbackQuery, prefix: &str) -> bool {
+    query
+        .data
+        .as_deref()
+        .is_some_and(|data| data.starts_with(prefix))
+}
+
+pub async fn subscribe_callback_handler(
+    bot: Bot,
+    query: CallbackQuery,
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let data = query.data.as_deref().unwrap_or_default();
+
+    let (broadcaster_id, source) = if let Some(v) = data.strip_prefix(SUBSCRIBE_CALLBACK_PREFIX) {
+        (v.to_string(), SubscriptionSource::Button)
+    } else if let Some(v) = data.strip_prefix(DEEP_LINK_CALLBACK_PREFIX) {
+        (v.to_string(), SubscriptionSource::DeepLink)
+    } else {
+        return Ok(());
+    };
+
+    let language = callback_language(&query).await;
+
+    let chat_id = match callback_chat_id(&bot, &query).await? {
+        Some(v) => v,
+        None => {
+            return match bot
+                .answer_callback_query(query.id)
+                .text(Text::NotAdmin.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    let text = match twitch_client.get_broadcaster_by_id(&broadcaster_id).await? {
+        Some(broadcaster) => {
+            let text = Text::SubscribedTo(&broadcaster.display_name).localize(language);
+
+            let thread_id = query
+                .message
+                .as_ref()
+                .and_then(|message| message.regular_message())
+                .and_then(message_thread_id);
+
+            subscription_manager
+                .subscribe(chat_id.0, thread_id, broadcaster, source)
+                .await;
+
+            remember_language_code(chat_id, Some(&query.from)).await;
+
+            text
+        }
+        None => Text::StreamerNotFound.localize(language),
+    };
+
+    match bot.answer_callback_query(query.id).text(text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+fn language_keyboard() -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(Language::ALL.map(|language| {
+        [InlineKeyboardButton::callback(
+            language.name(),
+            format!("{}{}", LANGUAGE_CALLBACK_PREFIX, language.code()),
+        )]
+    }))
+}
+
+pub async fn language_handler(bot: Bot, message: Message, args: String) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let chosen = match Language::from_code(args.trim()) {
+        Some(v) => v,
+        None => {
+            return match bot
+                .send_message(chat_id, Text::LanguageUsage.localize(language))
+                .reply_markup(language_keyboard())
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    if !ensure_can_manage(&bot, &message, language).await? {
+        return Ok(());
+    }
+
+    ChatRepository::set_language(chat_id.0, Some(chosen.code().to_string())).await?;
+
+    match bot
+        .send_message(chat_id, Text::LanguageSet.localize(chosen))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+pub async fn language_callback_handler(bot: Bot, query: CallbackQuery) -> BotHandlerInternal {
+    let chosen = match query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(LANGUAGE_CALLBACK_PREFIX))
+        .and_then(Language::from_code)
+    {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let language = callback_language(&query).await;
+
+    let chat_id = match callback_chat_id(&bot, &query).await? {
+        Some(v) => v,
+        None => {
+            return match bot
+                .answer_callback_query(query.id)
+                .text(Text::NotAdmin.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    ChatRepository::set_language(chat_id.0, Some(chosen.code().to_string())).await?;
+
+    bot.answer_callback_query(query.id.clone()).await?;
+
+    let message = match query.message.as_ref() {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    match bot
+        .edit_message_text(
+            message.chat().id,
+            message.id(),
+            Text::LanguageSet.localize(chosen),
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// A group upgraded to a supergroup gets a new id, the subscriptions move along.
+pub async fn migration_handler(
+    message: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> BotHandlerInternal {
+    if let Some(new_chat_id) = message.migrate_to_chat_id() {
+        subscription_manager
+            .migrate_chat(message.chat.id.0, new_chat_id.0)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Cleans up after the bot was kicked from a chat or blocked by the user.
+pub async fn my_chat_member_handler(
+    update: ChatMemberUpdated,
+    subscription_manager: Arc<SubscriptionManager>,
+) -> BotHandlerInternal {
+    if update.new_chat_member.is_present() {
+        return Ok(());
+    }
+
+    let reason = if update.new_chat_member.is_banned() {
+        "Bot was blocked or banned"
+    } else {
+        "Bot was removed"
+    };
+
+    subscription_manager
+        .remove_chat(
+            update.chat.id.0,
+            format!("{} by {}", reason, update.from.id),
+        )
+        .await;
+
+    Ok(())
+}
+
+/// A shareable card of a channel, showing its stream if it is live.
+fn channel_article(
+    language: Language,
+    channel: &Channel,
+    stream: Option<&Stream>,
+    subscribe_url: url::Url,
+) -> InlineQueryResult {
+    let display_name = channel.display_name.to_string();
+    let game_name = channel.game_name.to_string();
+
+    let (title, description, text) = match stream {
+        Some(stream) => (
+            format!("🔴 {}", display_name),
+            format!(
+                "🎮 {} · 👀 {}\n{}",
+                stream.game_name, stream.viewer_count, stream.title
+            ),
+            live_message_text(language, &display_name, Some(stream)),
+        ),
+        None => {
+            let mut text = Text::IsOffline(&html::escape(&display_name)).localize(language);
+
+            if !channel.title.is_empty() {
+                text.push_str(&format!("\n\n{}", html::escape(&channel.title)));
+            }
+
+            if !game_name.is_empty() {
+                text.push_str(&format!("\n🎮 {}", html::escape(&game_name)));
+            }
+
+            (
+                display_name.clone(),
+                format!("{} · {}", Text::Offline.localize(language), channel.title),
+                text,
+            )
+        }
+    };
+
+    let keyboard = InlineKeyboardMarkup::new([
+        [InlineKeyboardButton::url(
+            Text::WatchOnTwitch.localize(language),
+            channel_url(channel.broadcaster_login.as_str()),
+        )],
+        [InlineKeyboardButton::url(
+            Text::Subscribe.localize(language),
+            subscribe_url,
+        )],
+    ]);
+
+    let mut article = InlineQueryResultArticle::new(
+        channel.id.to_string(),
+        title,
+        InputMessageContent::Text(InputMessageContentText::new(text).parse_mode(ParseMode::Html)),
     )
+    .description(description)
+    .reply_markup(keyboard);
+
+    let thumbnail = match stream {
+        Some(stream) => thumbnail_url(stream),
+        None => url::Url::parse(&channel.thumbnail_url).ok(),
+    };
+
+    if let Some(thumbnail) = thumbnail {
+        article = article.thumbnail_url(thumbnail);
+    }
+
+    InlineQueryResult::Article(article)
 }
 
-pub async fn get_commands() -> Vec<BotCommand> {
-    vec![
-        BotCommand {
-            command: "start".into(),
-            description: "Start the bot".into(),
-        },
-        BotCommand {
-            command: "help".into(),
-            description: "Show help".into(),
-        },
-        BotCommand {
-            command: "subscribe".into(),
-            description: "Subscribe to the newsletter".into(),
-        },
-        BotCommand {
-            command: "unsubscribe".into(),
-            description: "Unsubscribe from the newsletter".into(),
+/// `@bot <name>` in any chat: searches channels to share them there.
+pub async fn inline_query_handler(
+    bot: Bot,
+    query: InlineQuery,
+    twitch_client: Arc<TwitchClient>,
+) -> BotHandlerInternal {
+    let language = language(query.from.id.into(), Some(&query.from)).await;
+
+    let search = query.query.trim();
+
+    let channels = if search.is_empty() {
+        Vec::new()
+    } else {
+        twitch_client
+            .search_channel_details(search, INLINE_RESULTS_LIMIT)
+            .await?
+    };
+
+    let live_ids = channels
+        .iter()
+        .filter(|channel| channel.is_live)
+        .map(|channel| channel.id.to_string())
+        .collect::<Vec<_>>();
+
+    let streams = twitch_client
+        .get_streams_cached(&live_ids)
+        .await?
+        .into_iter()
+        .map(|stream| (stream.user_id.to_string(), stream))
+        .collect::<HashMap<_, _>>();
+
+    let bot_url = bot.get_me().await?.tme_url();
+
+    let results = channels
+        .iter()
+        .map(|channel| {
+            let mut subscribe_url = bot_url.clone();
+            subscribe_url.query_pairs_mut().append_pair(
+                "start",
+                &format!(
+                    "{}{}",
+                    SUBSCRIBE_DEEP_LINK_PREFIX, channel.broadcaster_login
+                ),
+            );
+
+            channel_article(
+                language,
+                channel,
+                streams.get(channel.id.as_str()),
+                subscribe_url,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Live status changes quickly and the texts depend on the user's language
+    match bot
+        .answer_inline_query(query.id, results)
+        .cache_time(60)
+        .is_personal(true)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Chats that have subscriptions or settings and didn't block the bot.
+async fn known_chat_ids() -> mongodb::error::Result<BTreeSet<i64>> {
+    let mut chat_ids = SubscriptionRepository::distinct_chat_ids()
+        .await?
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+
+    chat_ids.extend(ChatRepository::active_chat_ids().await?);
+
+    for chat_id in ChatRepository::inactive_chat_ids().await? {
+        chat_ids.remove(&chat_id);
+    }
+
+    Ok(chat_ids)
+}
+
+pub async fn stats_handler(bot: Bot, message: Message) -> BotHandlerInternal {
+    let language = message_language(&message).await;
+
+    let text = Text::Stats {
+        chats: known_chat_ids().await?.len(),
+        subscriptions: SubscriptionRepository::count_active().await?,
+        streamers: SubscriptionRepository::distinct_broadcaster_ids()
+            .await?
+            .len(),
+        registrations: EventSubRepository::count_by_status("enabled").await?,
+        notifications_today: StatsRepository::get(&stats::notifications_sent(
+            Utc::now().date_naive(),
+        ))
+        .await?,
+        removed_chats: StatsRepository::get(REMOVED_CHATS).await?,
+    }
+    .localize(language);
+
+    match bot.send_message(message.chat.id, text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Sends the text to every known chat, editing a status message as it goes.
+pub async fn broadcast_handler(
+    bot: Bot,
+    message: Message,
+    subscription_manager: Arc<SubscriptionManager>,
+    text: String,
+) -> BotHandlerInternal {
+    let chat_id = message.chat.id;
+    let language = message_language(&message).await;
+
+    let text = text.trim();
+
+    if text.is_empty() {
+        return match bot
+            .send_message(chat_id, Text::BroadcastUsage.localize(language))
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Box::new(err)),
+        };
+    }
+
+    let chat_ids = known_chat_ids().await?;
+    let total = chat_ids.len();
+
+    let status = bot
+        .send_message(
+            chat_id,
+            Text::BroadcastProgress {
+                sent: 0,
+                failed: 0,
+                total,
+            }
+            .localize(language),
+        )
+        .await?;
+
+    let mut sent = 0;
+    let mut failed = 0;
+
+    for (index, recipient) in chat_ids.into_iter().enumerate() {
+        match bot.send_message(ChatId(recipient), text).await {
+            Ok(_) => sent += 1,
+            Err(err) => {
+                tracing::error!("Failed to broadcast to {}: {:?}", recipient, err);
+                failed += 1;
+
+                if is_chat_unreachable(&err) {
+                    subscription_manager
+                        .remove_chat(recipient, err.to_string())
+                        .await;
+                }
+            }
+        }
+
+        if (index + 1) % BROADCAST_PROGRESS_EVERY == 0 {
+            let progress = Text::BroadcastProgress {
+                sent,
+                failed,
+                total,
+            }
+            .localize(language);
+
+            if let Err(err) = bot.edit_message_text(chat_id, status.id, progress).await {
+                tracing::warn!("Failed to update broadcast progress: {:?}", err);
+            }
+        }
+
+        tokio::time::sleep(BROADCAST_DELAY).await;
+    }
+
+    match bot
+        .edit_message_text(
+            chat_id,
+            status.id,
+            Text::BroadcastDone {
+                sent,
+                failed,
+                total,
+            }
+            .localize(language),
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// Lists the subscriptions and settings of a chat, which for private chats
+/// is the user id.
+pub async fn whois_handler(bot: Bot, message: Message, args: String) -> BotHandlerInternal {
+    let language = message_language(&message).await;
+
+    let target = match args.trim().parse::<i64>() {
+        Ok(v) => v,
+        Err(_) => {
+            return match bot
+                .send_message(message.chat.id, Text::WhoisUsage.localize(language))
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Box::new(err)),
+            };
+        }
+    };
+
+    let mut subscriptions = SubscriptionRepository::all_by_chat(target).await?;
+    let settings = ChatRepository::get(target).await?;
+
+    subscriptions.sort_by_key(|sub| sub.broadcaster_display_name.to_lowercase());
+
+    let mut text = Text::WhoisHeader {
+        chat_id: target,
+        subscriptions: subscriptions.len(),
+    }
+    .localize(language);
+
+    if let Some(settings) = settings {
+        if !settings.active {
+            text.push_str(&format!("\n⚠️ {}", Text::WhoisInactive.localize(language)));
+        }
+
+        text.push_str(&format!(
+            "\n🌐 {} · 🕓 {}",
+            settings.language().name(),
+            settings.timezone().name()
+        ));
+
+        if let Some(quiet_hours) = settings.quiet_hours {
+            text.push_str(&format!(
+                " · 🌙 {} ({})",
+                quiet_hours,
+                settings.quiet_mode.as_str()
+            ));
+        }
+    }
+
+    if !subscriptions.is_empty() {
+        text.push('\n');
+    }
+
+    for sub in subscriptions {
+        let mut flags = String::new();
+
+        if sub.favorite {
+            flags.push_str(" ⭐");
+        }
+
+        if sub.notify_channel_updates {
+            flags.push_str(" ✏️");
+        }
+
+        if sub.template.is_some() {
+            flags.push_str(" 📝");
+        }
+
+        if !sub.active {
+            flags.push_str(" ⏸");
+        }
+
+        text.push_str(&format!(
+            "\n• {} ({}){}",
+            sub.broadcaster_display_name, sub.broadcaster_login, flags
+        ));
+    }
+
+    match bot.send_message(message.chat.id, text).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+fn get_admin_command_handler() -> BotHandler {
+    dptree::filter(|message: Message| is_admin(&message))
+        .filter_command::<AdminCommand>()
+        .endpoint(|bot, message, command, subscription_manager| async move {
+            match command {
+                AdminCommand::Stats => stats_handler(bot, message).await,
+                AdminCommand::Broadcast(text) => {
+                    broadcast_handler(bot, message, subscription_manager, text).await
+                }
+                AdminCommand::Whois(args) => whois_handler(bot, message, args).await,
+            }
+        })
+}
+
+fn get_command_handler() -> BotHandler {
+    dptree::entry().filter_command::<Command>().endpoint(
+        |bot, message, command, subscription_manager, twitch_client| async move {
+            match command {
+                Command::Start(payload) => {
+                    start_handler(bot, message, twitch_client, payload).await
+                }
+                Command::Help => help_message_handler(bot, message).await,
+                Command::Subscribe(username) => {
+                    subscribe_handler(bot, message, subscription_manager, twitch_client, username)
+                        .await
+                }
+                Command::Unsubscribe(username) => {
+                    unsubscribe_handler(bot, message, subscription_manager, username).await
+                }
+                Command::Updates(username) => updates_handler(bot, message, username).await,
+                Command::List => list_handler(bot, message, twitch_client).await,
+                Command::Live => live_handler(bot, message, twitch_client).await,
+                Command::Template(args) => template_handler(bot, message, args).await,
+                Command::Preview(username) => {
+                    preview_handler(bot, message, twitch_client, username).await
+                }
+                Command::Language(args) => language_handler(bot, message, args).await,
+                Command::Favorite(username) => favorite_handler(bot, message, username).await,
+                Command::Quiet(args) => quiet_handler(bot, message, args).await,
+                Command::Timezone(args) => timezone_handler(bot, message, args).await,
+            }
         },
+    )
+}
+
+pub async fn get_handler() -> BotHandler {
+    dptree::entry()
+        .branch(
+            Update::filter_message()
+                .filter(|message: Message| message.migrate_to_chat_id().is_some())
+                .endpoint(migration_handler),
+        )
+        .branch(Update::filter_message().chain(get_admin_command_handler()))
+        .branch(Update::filter_message().chain(get_command_handler()))
+        // Channels have no members to talk to the bot, commands come as posts
+        .branch(Update::filter_channel_post().chain(get_command_handler()))
+        .branch(Update::filter_my_chat_member().endpoint(my_chat_member_handler))
+        .branch(Update::filter_inline_query().endpoint(inline_query_handler))
+        .branch(
+            Update::filter_callback_query()
+                .branch(
+                    dptree::filter(|query: CallbackQuery| {
+                        has_callback_prefix(&query, SUBSCRIBE_CALLBACK_PREFIX)
+                            || has_callback_prefix(&query, DEEP_LINK_CALLBACK_PREFIX)
+                    })
+                    .endpoint(subscribe_callback_handler),
+                )
+                .branch(
+                    dptree::filter(|query: CallbackQuery| {
+                        has_callback_prefix(&query, UNSUBSCRIBE_CALLBACK_PREFIX)
+                    })
+                    .endpoint(unsubscribe_callback_handler),
+                )
+                .branch(
+                    dptree::filter(|query: CallbackQuery| {
+                        has_callback_prefix(&query, LIST_CALLBACK_PREFIX)
+                    })
+                    .endpoint(list_callback_handler),
+                )
+                .branch(
+                    dptree::filter(|query: CallbackQuery| {
+                        has_callback_prefix(&query, LANGUAGE_CALLBACK_PREFIX)
+                    })
+                    .endpoint(language_callback_handler),
+                ),
+        )
+}
+
+pub async fn get_commands(language: Language) -> Vec<BotCommand> {
+    [
+        ("start", Text::CommandStart),
+        ("help", Text::CommandHelp),
+        ("subscribe", Text::CommandSubscribe),
+        ("unsubscribe", Text::CommandUnsubscribe),
+        ("updates", Text::CommandUpdates),
+        ("list", Text::CommandList),
+        ("live", Text::CommandLive),
+        ("template", Text::CommandTemplate),
+        ("preview", Text::CommandPreview),
+        ("language", Text::CommandLanguage),
+        ("favorite", Text::CommandFavorite),
+        ("quiet", Text::CommandQuiet),
+        ("timezone", Text::CommandTimezone),
     ]
+    .into_iter()
+    .map(|(command, description)| BotCommand {
+        command: command.into(),
+        description: description.localize(language),
+    })
+    .collect()
+}
+
+pub async fn get_admin_commands(language: Language) -> Vec<BotCommand> {
+    let mut commands = get_commands(language).await;
+
+    commands.extend(
+        [
+            ("stats", Text::CommandStats),
+            ("broadcast", Text::CommandBroadcast),
+            ("whois", Text::CommandWhois),
+        ]
+        .into_iter()
+        .map(|(command, description)| BotCommand {
+            command: command.into(),
+            description: description.localize(language),
+        }),
+    );
+
+    commands
 }
 
 pub fn get_telegram_bot() -> Bot {
@@ -132,17 +1785,48 @@ pub fn get_telegram_bot() -> Bot {
         .cache_me()
 }
 
-pub async fn start_telegram_bot(subscription_manager: Arc<SubscriptionManager>) {
+pub async fn start_telegram_bot(
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+) {
     let bot = get_telegram_bot();
 
     let handler = get_handler().await;
-    let commands = get_commands().await;
 
     let _ = bot.delete_webhook().await;
-    let _ = bot.set_my_commands(commands).await;
+    let _ = bot
+        .set_my_commands(get_commands(Language::default()).await)
+        .await;
+
+    for language in Language::ALL {
+        let _ = bot
+            .set_my_commands(get_commands(language).await)
+            .language_code(language.code())
+            .await;
+    }
+
+    // Admins see their commands in the private chat with the bot
+    for admin_user_id in CONFIG.admin_user_ids.iter() {
+        let scope = BotCommandScope::Chat {
+            chat_id: Recipient::Id(ChatId(*admin_user_id as i64)),
+        };
+
+        let _ = bot
+            .set_my_commands(get_admin_commands(Language::default()).await)
+            .scope(scope.clone())
+            .await;
+
+        for language in Language::ALL {
+            let _ = bot
+                .set_my_commands(get_admin_commands(language).await)
+                .scope(scope.clone())
+                .language_code(language.code())
+                .await;
+        }
+    }
 
     let mut dispatcher = Dispatcher::builder(bot.clone(), handler)
-        .dependencies(dptree::deps![subscription_manager])
+        .dependencies(dptree::deps![subscription_manager, twitch_client])
         .build();
 
     let addr = ([0, 0, 0, 0], CONFIG.telegram_webhook_port).into();
@@ -0,0 +1,224 @@
+use std::fmt;
+
+use teloxide::utils::html;
+
+/// Placeholders a live notification template may use.
+pub const PLACEHOLDERS: &[&str] = &[
+    "display_name",
+    "login",
+    "title",
+    "category",
+    "viewers",
+    "url",
+];
+
+/// Telegram limits photo captions to this many characters.
+const MAX_TEMPLATE_LENGTH: usize = 1024;
+
+/// What the placeholders of a template are filled with.
+pub struct TemplateValues {
+    pub display_name: String,
+    pub login: String,
+    pub title: String,
+    pub category: String,
+    pub viewers: String,
+    pub url: String,
+}
+
+impl TemplateValues {
+    fn get(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "display_name" => Some(&self.display_name),
+            "login" => Some(&self.login),
+            "title" => Some(&self.title),
+            "category" => Some(&self.category),
+            "viewers" => Some(&self.viewers),
+            "url" => Some(&self.url),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Empty,
+    TooLong,
+    UnknownPlaceholder(String),
+    UnclosedPlaceholder,
+    UnopenedPlaceholder,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "The template is empty"),
+            Self::TooLong => write!(
+                f,
+                "The template is longer than {} characters",
+                MAX_TEMPLATE_LENGTH
+            ),
+            Self::UnknownPlaceholder(name) => write!(
+                f,
+                "Unknown placeholder {{{}}}, available are: {}",
+                name,
+                PLACEHOLDERS
+                    .iter()
+                    .map(|v| format!("{{{}}}", v))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::UnclosedPlaceholder => write!(f, "A placeholder is missing its closing }}"),
+            Self::UnopenedPlaceholder => write!(f, "A }} has no matching {{"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+fn parse(template: &str) -> Result<Vec<Part<'_>>, TemplateError> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(TemplateError::UnopenedPlaceholder);
+        }
+
+        let end = match rest[start + 1..].find(['{', '}']) {
+            Some(end) if rest[start + 1 + end..].starts_with('}') => start + 1 + end,
+            _ => return Err(TemplateError::UnclosedPlaceholder),
+        };
+
+        let name = &rest[start + 1..end];
+
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(TemplateError::UnknownPlaceholder(name.to_string()));
+        }
+
+        parts.push(Part::Text(&rest[..start]));
+        parts.push(Part::Placeholder(name));
+
+        rest = &rest[end + 1..];
+    }
+
+    parts.push(Part::Text(rest));
+
+    Ok(parts)
+}
+
+/// Checks a template before it is stored. The template itself is HTML, only
+/// substituted values get escaped.
+pub fn validate(template: &str) -> Result<(), TemplateError> {
+    if template.trim().is_empty() {
+        return Err(TemplateError::Empty);
+    }
+
+    if template.chars().count() > MAX_TEMPLATE_LENGTH {
+        return Err(TemplateError::TooLong);
+    }
+
+    parse(template).map(|_| ())
+}
+
+pub fn render(template: &str, values: &TemplateValues) -> Result<String, TemplateError> {
+    let mut text = String::new();
+
+    for part in parse(template)? {
+        match part {
+            Part::Text(v) => text.push_str(v),
+            Part::Placeholder(name) => {
+                text.push_str(&html::escape(values.get(name).unwrap_or_default()))
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> TemplateValues {
+        TemplateValues {
+            display_name: "<Streamer>".to_string(),
+            login: "streamer".to_string(),
+            title: "Tom & Jerry".to_string(),
+            category: "Just Chatting".to_string(),
+            viewers: "42".to_string(),
+            url: "https://twitch.tv/streamer".to_string(),
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let text = render("{login} plays {category} for {viewers}: {url}", &values()).unwrap();
+
+        assert_eq!(
+            text,
+            "streamer plays Just Chatting for 42: https://twitch.tv/streamer"
+        );
+    }
+
+    #[test]
+    fn escapes_substituted_values_only() {
+        let text = render("<b>{display_name}</b> {title}", &values()).unwrap();
+
+        assert_eq!(text, "<b>&lt;Streamer&gt;</b> Tom &amp; Jerry");
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        assert!(matches!(
+            validate("{game} is on"),
+            Err(TemplateError::UnknownPlaceholder(name)) if name == "game"
+        ));
+        assert!(matches!(
+            validate("{}"),
+            Err(TemplateError::UnknownPlaceholder(name)) if name.is_empty()
+        ));
+    }
+
+    #[test]
+    fn rejects_unclosed_placeholders() {
+        assert!(matches!(
+            validate("{title"),
+            Err(TemplateError::UnclosedPlaceholder)
+        ));
+        assert!(matches!(
+            validate("{title {url}"),
+            Err(TemplateError::UnclosedPlaceholder)
+        ));
+    }
+
+    #[test]
+    fn rejects_closing_brace_without_opening() {
+        assert!(matches!(
+            validate("title} is live"),
+            Err(TemplateError::UnopenedPlaceholder)
+        ));
+        assert!(matches!(
+            validate("{title}}"),
+            Err(TemplateError::UnopenedPlaceholder)
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_templates() {
+        assert!(matches!(validate(" \n "), Err(TemplateError::Empty)));
+    }
+
+    #[test]
+    fn limits_length_in_characters() {
+        assert!(validate(&"я".repeat(MAX_TEMPLATE_LENGTH)).is_ok());
+        assert!(matches!(
+            validate(&"a".repeat(MAX_TEMPLATE_LENGTH + 1)),
+            Err(TemplateError::TooLong)
+        ));
+    }
+}
@@ -0,0 +1,562 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use eyre::{Context, ContextCompat};
+use futures::{StreamExt as _, TryStreamExt as _};
+use serde::Serialize;
+use tokio::sync::RwLock;
+use twitch_api::{
+    HelixClient,
+    client::ClientDefault,
+    helix::{
+        ClientRequestError, HelixRequestDeleteError, HelixRequestGetError, HelixRequestPostError,
+        channels::ChannelInformation, search::Channel, streams::Stream, users::User,
+    },
+    types,
+};
+use twitch_oauth2::{AppAccessToken, TwitchToken as _, UserToken};
+
+use crate::config::CONFIG;
+
+#[derive(Clone, Debug)]
+pub struct Broadcaster {
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
+}
+
+/// What a channel tells about itself, for confirmation cards.
+pub struct BroadcasterProfile {
+    pub broadcaster: Broadcaster,
+    pub description: String,
+    pub profile_image_url: Option<String>,
+}
+
+impl From<User> for BroadcasterProfile {
+    fn from(user: User) -> Self {
+        Self {
+            broadcaster: Broadcaster {
+                id: user.id.to_string(),
+                login: user.login.to_string(),
+                display_name: user.display_name.to_string(),
+            },
+            description: user.description.unwrap_or_default(),
+            profile_image_url: user.profile_image_url,
+        }
+    }
+}
+
+/// How an access token is doing, for monitoring.
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenHealth {
+    pub healthy: bool,
+    pub expires_in_secs: u64,
+    pub refreshed_at: Option<String>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct RefreshState {
+    refreshed_at: Option<DateTime<Utc>>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+impl RefreshState {
+    fn record(&mut self, result: &Result<(), eyre::Report>) {
+        match result {
+            Ok(_) => {
+                *self = Self {
+                    refreshed_at: Some(Utc::now()),
+                    consecutive_failures: 0,
+                    last_error: None,
+                };
+            }
+            Err(err) => {
+                self.consecutive_failures += 1;
+                self.last_error = Some(format!("{:?}", err));
+            }
+        }
+    }
+
+    fn health(&self, expires_in: Duration) -> TokenHealth {
+        TokenHealth {
+            healthy: self.consecutive_failures == 0 && !expires_in.is_zero(),
+            expires_in_secs: expires_in.as_secs(),
+            refreshed_at: self.refreshed_at.map(|v| v.to_rfc3339()),
+            consecutive_failures: self.consecutive_failures,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+pub struct TwitchClient {
+    pub helix: HelixClient<'static, reqwest::Client>,
+    pub app_access_token: Arc<RwLock<AppAccessToken>>,
+    pub user_access_token: Option<Arc<RwLock<UserToken>>>,
+    refresh_state: RwLock<RefreshState>,
+    user_refresh_state: RwLock<RefreshState>,
+    /// Recently seen streams (or their absence) by broadcaster id
+    streams_cache: Arc<retainer::Cache<String, Option<Stream>>>,
+}
+
+/// The HTTP status of a failed Helix request.
+fn helix_status(err: &eyre::Report) -> Option<u16> {
+    let status = match err.downcast_ref::<ClientRequestError<reqwest::Error>>() {
+        Some(ClientRequestError::HelixRequestGetError(HelixRequestGetError::Error {
+            status,
+            ..
+        })) => status,
+        Some(ClientRequestError::HelixRequestPostError(HelixRequestPostError::Error {
+            status,
+            ..
+        })) => status,
+        Some(ClientRequestError::HelixRequestDeleteError(HelixRequestDeleteError::Error {
+            status,
+            ..
+        })) => status,
+        _ => return None,
+    };
+
+    Some(status.as_u16())
+}
+
+/// Whether Helix rejected the token we sent.
+pub fn is_unauthorized(err: &eyre::Report) -> bool {
+    helix_status(err) == Some(401)
+}
+
+pub fn is_not_found(err: &eyre::Report) -> bool {
+    helix_status(err) == Some(404)
+}
+
+impl TwitchClient {
+    const REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+    const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(30);
+    const STREAMS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+    pub async fn new() -> Result<Self, eyre::Report> {
+        let helix: HelixClient<_> = twitch_api::HelixClient::with_client(
+            <reqwest::Client>::default_client_with_name(Some(
+                "twitch-rs/eventsub"
+                    .parse()
+                    .wrap_err_with(|| "when creating header name")
+                    .unwrap(),
+            ))
+            .wrap_err_with(|| "when creating client")?,
+        );
+
+        let token = Self::get_app_access_token(&helix).await?;
+
+        let user_access_token = match CONFIG.twitch_user_access_token.clone() {
+            Some(access_token) => Some(
+                UserToken::from_existing(
+                    &helix,
+                    access_token.into(),
+                    CONFIG.twitch_user_refresh_token.clone().map(Into::into),
+                    Some(CONFIG.twitch_client_secret.clone().into()),
+                )
+                .await
+                .wrap_err("when validating user access token")?,
+            ),
+            None => None,
+        };
+
+        let streams_cache = Arc::new(retainer::Cache::new());
+
+        // Expired entries are only dropped by the monitor
+        let monitor = streams_cache.clone();
+        tokio::spawn(async move { monitor.monitor(10, 0.50, Self::STREAMS_CACHE_TTL).await });
+
+        Ok(Self {
+            helix,
+            app_access_token: Arc::new(RwLock::new(token)),
+            user_access_token: user_access_token.map(|token| Arc::new(RwLock::new(token))),
+            refresh_state: RwLock::new(RefreshState::default()),
+            user_refresh_state: RwLock::new(RefreshState::default()),
+            streams_cache,
+        })
+    }
+
+    async fn get_app_access_token(
+        helix: &HelixClient<'static, reqwest::Client>,
+    ) -> Result<AppAccessToken, eyre::Report> {
+        AppAccessToken::get_app_access_token(
+            helix,
+            CONFIG.twitch_client_id.clone().into(),
+            CONFIG.twitch_client_secret.clone().into(),
+            vec![],
+        )
+        .await
+        .wrap_err("when getting app access token")
+    }
+
+    /// Replaces the app access token with a new one. Does nothing if the
+    /// token is no longer `rejected`, i.e. someone else already refreshed it.
+    async fn refresh_app_access_token(&self, rejected: Option<&str>) -> Result<(), eyre::Report> {
+        let mut token = self.app_access_token.write().await;
+
+        if rejected.is_some_and(|rejected| token.access_token.secret() != rejected) {
+            return Ok(());
+        }
+
+        // Client credentials tokens come without a refresh token, a new one
+        // has to be requested instead
+        let result = Self::get_app_access_token(&self.helix)
+            .await
+            .map(|new_token| *token = new_token);
+
+        if result.is_ok() {
+            tracing::info!(
+                "Refreshed app access token, expires in {:?}",
+                token.expires_in()
+            );
+        }
+
+        self.refresh_state.write().await.record(&result);
+
+        result
+    }
+
+    /// The user access token, which only the WebSocket transport needs.
+    pub fn user_token(&self) -> Result<&RwLock<UserToken>, eyre::Report> {
+        self.user_access_token
+            .as_deref()
+            .wrap_err("TWITCH_USER_ACCESS_TOKEN is not set")
+    }
+
+    /// Refreshes the user access token with its refresh token. Does nothing
+    /// if the token is no longer `rejected`, like for the app access token.
+    async fn refresh_user_access_token(&self, rejected: Option<&str>) -> Result<(), eyre::Report> {
+        let mut token = self.user_token()?.write().await;
+
+        if rejected.is_some_and(|rejected| token.access_token.secret() != rejected) {
+            return Ok(());
+        }
+
+        let result = token
+            .refresh_token(&self.helix)
+            .await
+            .wrap_err("when refreshing user access token");
+
+        if result.is_ok() {
+            tracing::info!(
+                "Refreshed user access token, expires in {:?}",
+                token.expires_in()
+            );
+        }
+
+        self.user_refresh_state.write().await.record(&result);
+
+        result
+    }
+
+    /// Refreshes the app access token shortly before it expires.
+    pub async fn keep_app_access_token_fresh(&self) {
+        loop {
+            let expires_in = self.app_access_token.read().await.expires_in();
+
+            tokio::time::sleep(expires_in.saturating_sub(Self::REFRESH_MARGIN)).await;
+
+            if let Err(err) = self.refresh_app_access_token(None).await {
+                tracing::error!("Failed to refresh app access token: {:?}", err);
+
+                tokio::time::sleep(Self::REFRESH_RETRY_DELAY).await;
+            }
+        }
+    }
+
+    /// Refreshes the user access token shortly before it expires, if there
+    /// is one.
+    pub async fn keep_user_access_token_fresh(&self) {
+        let user_access_token = match self.user_access_token.as_ref() {
+            Some(v) => v,
+            None => return,
+        };
+
+        loop {
+            let expires_in = user_access_token.read().await.expires_in();
+
+            tokio::time::sleep(expires_in.saturating_sub(Self::REFRESH_MARGIN)).await;
+
+            if let Err(err) = self.refresh_user_access_token(None).await {
+                tracing::error!("Failed to refresh user access token: {:?}", err);
+
+                tokio::time::sleep(Self::REFRESH_RETRY_DELAY).await;
+            }
+        }
+    }
+
+    /// Runs a request with the app access token, once more with a fresh
+    /// token if Helix says the current one is no good.
+    pub async fn with_app_access_token<T, F, Fut>(&self, request: F) -> Result<T, eyre::Report>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, eyre::Report>>,
+    {
+        let sent = self
+            .app_access_token
+            .read()
+            .await
+            .access_token
+            .secret()
+            .to_string();
+
+        match request().await {
+            Err(err) if is_unauthorized(&err) => {
+                tracing::warn!("App access token was rejected, refreshing");
+
+                self.refresh_app_access_token(Some(&sent)).await?;
+
+                request().await
+            }
+            result => result,
+        }
+    }
+
+    /// Same as `with_app_access_token`, for the user access token.
+    pub async fn with_user_access_token<T, F, Fut>(&self, request: F) -> Result<T, eyre::Report>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, eyre::Report>>,
+    {
+        let sent = self
+            .user_token()?
+            .read()
+            .await
+            .access_token
+            .secret()
+            .to_string();
+
+        match request().await {
+            Err(err) if is_unauthorized(&err) => {
+                tracing::warn!("User access token was rejected, refreshing");
+
+                self.refresh_user_access_token(Some(&sent)).await?;
+
+                request().await
+            }
+            result => result,
+        }
+    }
+
+    pub async fn token_health(&self) -> TokenHealth {
+        let expires_in = self.app_access_token.read().await.expires_in();
+
+        self.refresh_state.read().await.health(expires_in)
+    }
+
+    pub async fn user_token_health(&self) -> Option<TokenHealth> {
+        let expires_in = self.user_access_token.as_ref()?.read().await.expires_in();
+
+        Some(self.user_refresh_state.read().await.health(expires_in))
+    }
+
+    pub async fn get_broadcaster_by_login(
+        &self,
+        login: &str,
+    ) -> Result<Option<Broadcaster>, eyre::Report> {
+        let user = self
+            .with_app_access_token(|| async {
+                self.helix
+                    .get_user_from_login(login, &*self.app_access_token.read().await)
+                    .await
+                    .wrap_err("when getting user")
+            })
+            .await?;
+
+        Ok(user.map(|user| Broadcaster {
+            id: user.id.to_string(),
+            login: user.login.to_string(),
+            display_name: user.display_name.to_string(),
+        }))
+    }
+
+    pub async fn get_broadcaster_by_id(
+        &self,
+        broadcaster_id: &str,
+    ) -> Result<Option<Broadcaster>, eyre::Report> {
+        let user = self
+            .with_app_access_token(|| async {
+                self.helix
+                    .get_user_from_id(broadcaster_id, &*self.app_access_token.read().await)
+                    .await
+                    .wrap_err("when getting user")
+            })
+            .await?;
+
+        Ok(user.map(|user| Broadcaster {
+            id: user.id.to_string(),
+            login: user.login.to_string(),
+            display_name: user.display_name.to_string(),
+        }))
+    }
+
+    /// Looks a channel up by id, or by login if no channel has such an id.
+    pub async fn get_broadcaster_profile(
+        &self,
+        login_or_id: &str,
+    ) -> Result<Option<BroadcasterProfile>, eyre::Report> {
+        if login_or_id.chars().all(|c| c.is_ascii_digit()) {
+            let user = self
+                .with_app_access_token(|| async {
+                    self.helix
+                        .get_user_from_id(login_or_id, &*self.app_access_token.read().await)
+                        .await
+                        .wrap_err("when getting user")
+                })
+                .await?;
+
+            if let Some(user) = user {
+                return Ok(Some(user.into()));
+            }
+        }
+
+        let user = self
+            .with_app_access_token(|| async {
+                self.helix
+                    .get_user_from_login(login_or_id, &*self.app_access_token.read().await)
+                    .await
+                    .wrap_err("when getting user")
+            })
+            .await?;
+
+        Ok(user.map(BroadcasterProfile::from))
+    }
+
+    /// Channels matching the query, with their live status and the title and
+    /// category of the current or last stream.
+    pub async fn search_channel_details(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Channel>, eyre::Report> {
+        self.with_app_access_token(|| async {
+            self.helix
+                .search_channels(query, &*self.app_access_token.read().await)
+                .take(limit)
+                .try_collect::<Vec<_>>()
+                .await
+                .wrap_err("when searching channels")
+        })
+        .await
+    }
+
+    /// Returns channels whose name resembles the query, best matches first.
+    pub async fn search_channels(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Broadcaster>, eyre::Report> {
+        let channels = self.search_channel_details(query, limit).await?;
+
+        Ok(channels
+            .into_iter()
+            .map(|channel| Broadcaster {
+                id: channel.id.to_string(),
+                login: channel.broadcaster_login.to_string(),
+                display_name: channel.display_name.to_string(),
+            })
+            .collect())
+    }
+
+    /// Like `get_streams`, but answers from what was fetched in the last
+    /// minute where possible. Good enough for showing, not for detecting.
+    pub async fn get_streams_cached(
+        &self,
+        broadcaster_ids: &[String],
+    ) -> Result<Vec<Stream>, eyre::Report> {
+        let mut streams = Vec::new();
+        let mut missing = Vec::new();
+
+        for broadcaster_id in broadcaster_ids {
+            match self.streams_cache.get(broadcaster_id).await {
+                Some(entry) => streams.extend(Option::<Stream>::clone(&entry)),
+                None => missing.push(broadcaster_id.clone()),
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(streams);
+        }
+
+        let mut fetched = self
+            .get_streams(&missing)
+            .await?
+            .into_iter()
+            .map(|stream| (stream.user_id.to_string(), stream))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        for broadcaster_id in missing {
+            let stream = fetched.remove(&broadcaster_id);
+
+            self.streams_cache
+                .insert(broadcaster_id, stream.clone(), Self::STREAMS_CACHE_TTL)
+                .await;
+
+            streams.extend(stream);
+        }
+
+        Ok(streams)
+    }
+
+    /// Returns the title, category and such of those broadcasters.
+    pub async fn get_channels(
+        &self,
+        broadcaster_ids: &[String],
+    ) -> Result<Vec<ChannelInformation>, eyre::Report> {
+        let ids = broadcaster_ids
+            .iter()
+            .map(|id| types::UserId::from(id.clone()))
+            .collect::<Vec<_>>();
+
+        let mut channels = Vec::new();
+
+        // Helix accepts at most 100 ids per request
+        for chunk in ids.chunks(100) {
+            let chunk_channels = self
+                .with_app_access_token(|| async {
+                    self.helix
+                        .get_channels_from_ids(chunk, &*self.app_access_token.read().await)
+                        .await
+                        .wrap_err("when getting channels")
+                })
+                .await?;
+
+            channels.extend(chunk_channels);
+        }
+
+        Ok(channels)
+    }
+
+    /// Returns the streams of those broadcasters that are live right now.
+    pub async fn get_streams(
+        &self,
+        broadcaster_ids: &[String],
+    ) -> Result<Vec<Stream>, eyre::Report> {
+        let ids = broadcaster_ids
+            .iter()
+            .map(|id| types::UserId::from(id.clone()))
+            .collect::<Vec<_>>();
+
+        let mut streams = Vec::new();
+
+        // Helix accepts at most 100 ids per request
+        for chunk in ids.chunks(100) {
+            let chunk_streams = self
+                .with_app_access_token(|| async {
+                    self.helix
+                        .get_streams_from_ids(chunk, &*self.app_access_token.read().await)
+                        .try_collect::<Vec<_>>()
+                        .await
+                        .wrap_err("when getting streams")
+                })
+                .await?;
+
+            streams.extend(chunk_streams);
+        }
+
+        Ok(streams)
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use twitch_api::helix::streams::Stream;
+
+use crate::{
+    config::CONFIG, notifier::Notifier, subscription_manager::SubscriptionManager,
+    twitch_client::TwitchClient, twitch_webhook::RegistrationSupervisor,
+};
+
+/// Detects streams going online and offline by polling Helix, for
+/// broadcasters EventSub doesn't deliver events for.
+pub struct TwitchPoller {
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+    notifier: Arc<Notifier>,
+    // Without registrations every followed broadcaster is polled
+    registrations: Option<Arc<RegistrationSupervisor>>,
+}
+
+/// What the previous polls saw.
+#[derive(Default)]
+struct PollState {
+    /// Broadcasters polled at least once, so their first poll doesn't
+    /// announce streams that were already running.
+    known: HashSet<String>,
+    /// Names of the broadcasters that were live, for the offline message.
+    live: HashMap<String, String>,
+}
+
+impl TwitchPoller {
+    pub fn new(
+        subscription_manager: Arc<SubscriptionManager>,
+        twitch_client: Arc<TwitchClient>,
+        notifier: Arc<Notifier>,
+        registrations: Option<Arc<RegistrationSupervisor>>,
+    ) -> Self {
+        Self {
+            subscription_manager,
+            twitch_client,
+            notifier,
+            registrations,
+        }
+    }
+
+    async fn polled(&self) -> HashSet<String> {
+        let registered = match self.registrations.as_ref() {
+            Some(registrations) => registrations.registered().await,
+            None => HashSet::new(),
+        };
+
+        self.subscription_manager
+            .subscriptions
+            .read()
+            .await
+            .keys()
+            .filter(|broadcaster_id| !registered.contains(*broadcaster_id))
+            .cloned()
+            .collect()
+    }
+
+    async fn poll(&self, state: &mut PollState) -> Result<(), eyre::Report> {
+        let polled = self.polled().await;
+
+        // Whoever left polling is EventSub's (or nobody's) concern now
+        state
+            .known
+            .retain(|broadcaster_id| polled.contains(broadcaster_id));
+        state
+            .live
+            .retain(|broadcaster_id, _| polled.contains(broadcaster_id));
+
+        if polled.is_empty() {
+            return Ok(());
+        }
+
+        let broadcaster_ids = polled.iter().cloned().collect::<Vec<_>>();
+
+        let streams = self
+            .twitch_client
+            .get_streams(&broadcaster_ids)
+            .await?
+            .into_iter()
+            .map(|stream| (stream.user_id.to_string(), stream))
+            .collect::<HashMap<String, Stream>>();
+
+        for (broadcaster_id, stream) in streams.iter() {
+            if state.live.contains_key(broadcaster_id) {
+                continue;
+            }
+
+            state
+                .live
+                .insert(broadcaster_id.clone(), stream.user_name.to_string());
+
+            if !state.known.contains(broadcaster_id) {
+                continue;
+            }
+
+            let notifier = self.notifier.clone();
+            let broadcaster_id = broadcaster_id.clone();
+            let broadcaster_login = stream.user_login.to_string();
+            let broadcaster_name = stream.user_name.to_string();
+            let started_at = stream.started_at.to_string();
+
+            tokio::spawn(async move {
+                notifier
+                    .stream_online(
+                        broadcaster_id,
+                        broadcaster_login,
+                        broadcaster_name,
+                        started_at,
+                    )
+                    .await
+            });
+        }
+
+        let ended = state
+            .live
+            .keys()
+            .filter(|broadcaster_id| !streams.contains_key(*broadcaster_id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for broadcaster_id in ended {
+            let broadcaster_name = state.live.remove(&broadcaster_id).unwrap_or_default();
+
+            let notifier = self.notifier.clone();
+
+            tokio::spawn(async move {
+                notifier
+                    .stream_offline(broadcaster_id, broadcaster_name)
+                    .await
+            });
+        }
+
+        state.known.extend(polled);
+
+        Ok(())
+    }
+
+    pub async fn start(&self) {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(CONFIG.twitch_polling_interval));
+        let mut state = PollState::default();
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = self.poll(&mut state).await {
+                tracing::error!("Failed to poll streams: {:?}", err);
+            }
+        }
+    }
+}
@@ -1,99 +1,397 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
-    Extension, Router,
+    Extension, Json, Router,
     body::HttpBody,
     http::{self, StatusCode},
     response::IntoResponse,
-    routing::post,
+    routing::{get, post},
 };
-use eyre::{Context, ContextCompat};
+use eyre::Context;
 use futures::TryStreamExt as _;
 use http_body_util::BodyExt as _;
-use teloxide::prelude::Requester as _;
-use tokio::{net::TcpListener, sync::RwLock};
+use mongodb::bson::DateTime;
+use serde::Serialize;
+use tokio::{net::TcpListener, sync::RwLock, task::JoinHandle};
 use tower_http::trace::TraceLayer;
 use twitch_api::{
     HelixClient,
-    client::ClientDefault,
     eventsub::{
-        Event, EventType, Status,
-        stream::{StreamOnlineV1, StreamOnlineV1Payload},
+        Event, EventSubSubscription, EventSubscription, Status, Transport, TransportResponse,
+        channel::{ChannelRaidV1, ChannelRaidV1Payload, ChannelUpdateV2, ChannelUpdateV2Payload},
+        stream::{StreamOfflineV1, StreamOfflineV1Payload, StreamOnlineV1, StreamOnlineV1Payload},
     },
+    types,
 };
-use twitch_oauth2::AppAccessToken;
+use twitch_oauth2::TwitchToken;
 
 use crate::{
-    config::CONFIG, subscription_manager::SubscriptionManager, telegram_bot::get_telegram_bot,
+    config::{CONFIG, EventSubTransport},
+    notifier::Notifier,
+    repositories::eventsub::EventSubRepository,
+    subscription_manager::SubscriptionManager,
+    twitch_client::{Broadcaster, TokenHealth, TwitchClient, is_not_found},
+    twitch_poller::TwitchPoller,
+    twitch_websocket::TwitchWebSocketClient,
 };
 
+/// Where Twitch delivers events to, along with the token allowed to manage
+/// subscriptions for it.
+#[derive(Clone)]
+pub enum EventSubTarget {
+    Webhook {
+        twitch_client: Arc<TwitchClient>,
+        callback: String,
+    },
+    // WebSocket subscriptions can only be created with a user access token
+    WebSocket {
+        twitch_client: Arc<TwitchClient>,
+        session_id: String,
+    },
+}
+
+impl EventSubTarget {
+    fn transport(&self) -> Transport {
+        match self {
+            Self::Webhook { callback, .. } => {
+                Transport::webhook(callback.clone(), CONFIG.twitch_signing_secret.clone())
+            }
+            Self::WebSocket { session_id, .. } => Transport::websocket(session_id.clone()),
+        }
+    }
+
+    fn matches(&self, transport: &TransportResponse) -> bool {
+        match self {
+            Self::Webhook { callback, .. } => transport
+                .as_webhook()
+                .is_some_and(|webhook| &webhook.callback == callback),
+            Self::WebSocket { session_id, .. } => transport
+                .as_websocket()
+                .is_some_and(|websocket| &websocket.session_id == session_id),
+        }
+    }
+
+    fn same_transport(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Webhook { callback: a, .. }, Self::Webhook { callback: b, .. }) => a == b,
+            (Self::WebSocket { session_id: a, .. }, Self::WebSocket { session_id: b, .. }) => {
+                a == b
+            }
+            _ => false,
+        }
+    }
+}
+
 pub async fn eventsub_register(
-    token: Arc<RwLock<AppAccessToken>>,
-    login: String,
-    webhook_url: String,
+    target: EventSubTarget,
+    broadcaster_id: String,
 ) -> Result<(), eyre::Report> {
-    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    match &target {
+        EventSubTarget::Webhook { twitch_client, .. } => {
+            twitch_client
+                .with_app_access_token(|| async {
+                    eventsub_register_with(
+                        &*twitch_client.app_access_token.read().await,
+                        &target,
+                        broadcaster_id.clone(),
+                    )
+                    .await
+                })
+                .await
+        }
+        EventSubTarget::WebSocket { twitch_client, .. } => {
+            twitch_client
+                .with_user_access_token(|| async {
+                    eventsub_register_with(
+                        &*twitch_client.user_token()?.read().await,
+                        &target,
+                        broadcaster_id.clone(),
+                    )
+                    .await
+                })
+                .await
+        }
+    }
+}
 
+async fn eventsub_register_with<T>(
+    token: &T,
+    target: &EventSubTarget,
+    broadcaster_id: String,
+) -> Result<(), eyre::Report>
+where
+    T: TwitchToken + Send + Sync,
+{
     let client: HelixClient<'_, reqwest::Client> = HelixClient::new();
 
-    let channel_information = client
-        .get_channel_from_login(&login, &*token.read().await)
-        .await
-        .wrap_err("when getting channel")?
-        .wrap_err("when getting channel")?;
-
-    let broadcaster_id = channel_information.broadcaster_id;
-
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(24 * 60 * 60));
-
-    loop {
-        interval.tick().await;
-
-        let subs = client
-            .get_eventsub_subscriptions(Status::Enabled, None, None, &*token.read().await)
-            .map_ok(|events| {
-                futures::stream::iter(events.subscriptions.into_iter().map(Ok::<_, eyre::Report>))
-            })
-            .try_flatten()
-            .try_filter(|event| futures::future::ready(event.transport.is_webhook()))
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        let online_exists = subs.iter().any(|sub| {
-            sub.transport.as_webhook().unwrap().callback == webhook_url
-                && sub.type_ == EventType::StreamOnline
-                && sub.version == "1"
-                && sub
-                    .condition
-                    .as_object()
-                    .expect("a stream.online did not contain broadcaster")
-                    .get("broadcaster_user_id")
-                    .unwrap()
-                    .as_str()
-                    == Some(broadcaster_id.as_str())
-        });
+    let (subs, cost) = eventsub_subscriptions(&client, token, None, target).await?;
+
+    // Ones still waiting for the webhook verification count as well, creating
+    // them again would only get a conflict
+    let subs = subs
+        .into_iter()
+        .filter(|sub| {
+            matches!(
+                sub.status,
+                Status::Enabled | Status::WebhookCallbackVerificationPending
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let broadcaster_user_id = types::UserId::from(broadcaster_id.clone());
+
+    eventsub_ensure(
+        &client,
+        token,
+        target,
+        &subs,
+        &cost,
+        &broadcaster_id,
+        StreamOnlineV1::broadcaster_user_id(broadcaster_user_id.clone()),
+    )
+    .await
+    .wrap_err("when registering online event")?;
+
+    eventsub_ensure(
+        &client,
+        token,
+        target,
+        &subs,
+        &cost,
+        &broadcaster_id,
+        StreamOfflineV1::broadcaster_user_id(broadcaster_user_id.clone()),
+    )
+    .await
+    .wrap_err("when registering offline event")?;
 
-        let transport = twitch_api::eventsub::Transport::webhook(
-            webhook_url.clone(),
-            CONFIG.twitch_signing_secret.clone(),
+    eventsub_ensure(
+        &client,
+        token,
+        target,
+        &subs,
+        &cost,
+        &broadcaster_id,
+        ChannelUpdateV2::broadcaster_user_id(broadcaster_user_id.clone()),
+    )
+    .await
+    .wrap_err("when registering channel update event")?;
+
+    eventsub_ensure(
+        &client,
+        token,
+        target,
+        &subs,
+        &cost,
+        &broadcaster_id,
+        ChannelRaidV1::from_broadcaster_user_id(broadcaster_user_id),
+    )
+    .await
+    .wrap_err("when registering raid event")?;
+
+    Ok(())
+}
+
+/// How much of the EventSub budget our subscriptions take up. Subscriptions
+/// to broadcasters who haven't authorized the app cost 1 each.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EventSubCost {
+    pub total: u64,
+    pub max_total: u64,
+}
+
+impl EventSubCost {
+    pub fn exceeded(&self) -> bool {
+        self.max_total > 0 && self.total >= self.max_total
+    }
+}
+
+#[derive(Debug)]
+pub struct CostLimitExceeded(EventSubCost);
+
+impl std::fmt::Display for CostLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "EventSub cost limit exceeded ({} of {})",
+            self.0.total, self.0.max_total
+        )
+    }
+}
+
+impl std::error::Error for CostLimitExceeded {}
+
+/// Lists the subscriptions Twitch has for our transport.
+async fn eventsub_subscriptions<T>(
+    client: &HelixClient<'_, reqwest::Client>,
+    token: &T,
+    status: Option<Status>,
+    target: &EventSubTarget,
+) -> Result<(Vec<EventSubSubscription>, EventSubCost), eyre::Report>
+where
+    T: TwitchToken + Send + Sync,
+{
+    let mut pages = std::pin::pin!(client.get_eventsub_subscriptions(status, None, None, token));
+
+    let mut subs = Vec::new();
+    let mut cost = EventSubCost::default();
+
+    while let Some(page) = pages.try_next().await? {
+        cost = EventSubCost {
+            total: page.total_cost as u64,
+            max_total: page.max_total_cost as u64,
+        };
+
+        subs.extend(
+            page.subscriptions
+                .into_iter()
+                .filter(|sub| target.matches(&sub.transport)),
         );
+    }
 
-        if !online_exists {
-            client
-                .create_eventsub_subscription(
-                    StreamOnlineV1::broadcaster_user_id(broadcaster_id.clone()),
-                    transport.clone(),
-                    &*token.read().await,
-                )
+    Ok((subs, cost))
+}
+
+/// Creates the subscription unless Twitch already has it, and records it.
+async fn eventsub_ensure<T, E>(
+    client: &HelixClient<'_, reqwest::Client>,
+    token: &T,
+    target: &EventSubTarget,
+    subs: &[EventSubSubscription],
+    cost: &EventSubCost,
+    broadcaster_id: &str,
+    subscription: E,
+) -> Result<(), eyre::Report>
+where
+    T: TwitchToken + Send + Sync,
+    E: EventSubscription + Send,
+{
+    let existing = subs.iter().find(|sub| {
+        sub.type_ == E::EVENT_TYPE
+            && sub.version == E::VERSION
+            && condition_broadcaster_id(&sub.condition) == Some(broadcaster_id)
+    });
+
+    let (subscription_id, status, version, cost) = match existing {
+        Some(sub) => (
+            sub.id.to_string(),
+            serialized_name(&sub.status),
+            sub.version.clone(),
+            sub.cost,
+        ),
+        None if cost.exceeded() => return Err(CostLimitExceeded(*cost).into()),
+        None => {
+            let created = client
+                .create_eventsub_subscription(subscription, target.transport(), token)
+                .await?;
+
+            (
+                created.id.to_string(),
+                serialized_name(&created.status),
+                created.version,
+                created.cost,
+            )
+        }
+    };
+
+    EventSubRepository::upsert(
+        broadcaster_id.to_string(),
+        subscription_id,
+        serialized_name(&E::EVENT_TYPE),
+        version,
+        status,
+        cost as u64,
+    )
+    .await
+    .wrap_err("when saving registration")?;
+
+    Ok(())
+}
+
+pub async fn eventsub_unregister(
+    target: &EventSubTarget,
+    subscription_id: String,
+) -> Result<(), eyre::Report> {
+    let client: HelixClient<'_, reqwest::Client> = HelixClient::new();
+
+    let id = types::EventSubId::from(subscription_id.clone());
+
+    let result = match target {
+        EventSubTarget::Webhook { twitch_client, .. } => {
+            twitch_client
+                .with_app_access_token(|| async {
+                    client
+                        .delete_eventsub_subscription(
+                            id.clone(),
+                            &*twitch_client.app_access_token.read().await,
+                        )
+                        .await
+                        .wrap_err("when deleting subscription")
+                })
                 .await
-                .wrap_err_with(|| "when registering online event")?;
         }
-    }
+        EventSubTarget::WebSocket { twitch_client, .. } => {
+            twitch_client
+                .with_user_access_token(|| async {
+                    client
+                        .delete_eventsub_subscription(
+                            id.clone(),
+                            &*twitch_client.user_token()?.read().await,
+                        )
+                        .await
+                        .wrap_err("when deleting subscription")
+                })
+                .await
+        }
+    };
+
+    let result = match result {
+        // Already gone on Twitch, e.g. revoked
+        Err(err) if is_not_found(&err) => Ok(()),
+        result => result,
+    };
+
+    // The row goes either way, the sweep deletes whatever is left on Twitch
+    EventSubRepository::delete_by_subscription_id(subscription_id)
+        .await
+        .wrap_err("when deleting registration")?;
+
+    result
+}
+
+fn condition_broadcaster_id(condition: &serde_json::Value) -> Option<&str> {
+    condition
+        .get("broadcaster_user_id")
+        .or_else(|| condition.get("from_broadcaster_user_id"))?
+        .as_str()
+}
+
+/// The event types `eventsub_register` subscribes every broadcaster to.
+fn event_types() -> [String; 4] {
+    [
+        serialized_name(&StreamOnlineV1::EVENT_TYPE),
+        serialized_name(&StreamOfflineV1::EVENT_TYPE),
+        serialized_name(&ChannelUpdateV2::EVENT_TYPE),
+        serialized_name(&ChannelRaidV1::EVENT_TYPE),
+    ]
+}
+
+/// Returns the name Twitch uses for an EventSub enum value, e.g. `stream.online`.
+fn serialized_name<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
 }
 
 pub async fn twitch_eventsub(
     Extension(cache): Extension<Arc<retainer::Cache<http::HeaderValue, ()>>>,
-    Extension(subscription_manager): Extension<Arc<SubscriptionManager>>,
+    Extension(notifier): Extension<Arc<Notifier>>,
+    Extension(registrations): Extension<Arc<RegistrationSupervisor>>,
     request: http::Request<axum::body::Body>,
 ) -> impl IntoResponse {
     const MAX_ALLOWED_RESPONSE_SIZE: u64 = 64 * 1024;
@@ -126,12 +424,45 @@ pub async fn twitch_eventsub(
     let event = Event::parse_http(&request).unwrap();
 
     if let Some(ver) = event.get_verification_request() {
+        // Twitch enables the subscription once the challenge is answered
+        let subscription_id = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|payload| payload["subscription"]["id"].as_str().map(str::to_string));
+
+        if let Some(subscription_id) = subscription_id {
+            tokio::spawn(async move {
+                if let Err(err) = EventSubRepository::set_status(
+                    subscription_id.clone(),
+                    serialized_name(&Status::Enabled),
+                )
+                .await
+                {
+                    tracing::error!(
+                        "Failed to update registration {}: {:?}",
+                        subscription_id,
+                        err
+                    );
+                }
+            });
+        }
+
         return (StatusCode::OK, ver.challenge.clone());
     }
 
     if event.is_revocation() {
+        if let Err(err) = dispatch_revocation(&body, "/subscription", registrations, notifier) {
+            tracing::error!("Failed to parse revocation: {:?}", err);
+        }
+
         return (StatusCode::OK, "".to_string());
     }
+    dispatch_event(event, notifier);
+
+    (StatusCode::OK, String::default())
+}
+
+/// Hands a notification over to the notifier, whichever transport it came from.
+pub fn dispatch_event(event: Event, notifier: Arc<Notifier>) {
     use twitch_api::eventsub::{Message as M, Payload as P};
 
     tracing::info!("Event: {:?}", event);
@@ -140,54 +471,679 @@ pub async fn twitch_eventsub(
         Event::StreamOnlineV1(P {
             message:
                 M::Notification(StreamOnlineV1Payload {
+                    broadcaster_user_id,
+                    broadcaster_user_login,
                     broadcaster_user_name,
+                    started_at,
                     ..
                 }),
             ..
         }) => {
-            let bot = get_telegram_bot();
-
-            let subscriptions = subscription_manager.subscriptions.read().await;
-            let user_ids = match subscriptions.get(&broadcaster_user_name.to_string()) {
-                Some(v) => v,
-                None => return (StatusCode::OK, String::default()),
-            };
-
-            for user_id in user_ids.iter() {
-                if let Err(err) = bot
-                    .send_message(
-                        user_id.to_string(),
-                        format!("Streamer {} is now live! ()", broadcaster_user_name,),
+            tokio::spawn(async move {
+                notifier
+                    .stream_online(
+                        broadcaster_user_id.to_string(),
+                        broadcaster_user_login.to_string(),
+                        broadcaster_user_name.to_string(),
+                        started_at.to_string(),
                     )
                     .await
-                {
-                    tracing::error!("Failed to send message to {}: {:?}", user_id, err);
+            });
+        }
+        Event::StreamOfflineV1(P {
+            message:
+                M::Notification(StreamOfflineV1Payload {
+                    broadcaster_user_id,
+                    broadcaster_user_name,
+                    ..
+                }),
+            ..
+        }) => {
+            tokio::spawn(async move {
+                notifier
+                    .stream_offline(
+                        broadcaster_user_id.to_string(),
+                        broadcaster_user_name.to_string(),
+                    )
+                    .await
+            });
+        }
+        Event::ChannelUpdateV2(P {
+            message:
+                M::Notification(ChannelUpdateV2Payload {
+                    broadcaster_user_id,
+                    broadcaster_user_name,
+                    title,
+                    category_name,
+                    ..
+                }),
+            ..
+        }) => {
+            tokio::spawn(async move {
+                notifier
+                    .channel_update(
+                        broadcaster_user_id.to_string(),
+                        broadcaster_user_name.to_string(),
+                        title,
+                        category_name,
+                    )
+                    .await
+            });
+        }
+        Event::ChannelRaidV1(P {
+            message:
+                M::Notification(ChannelRaidV1Payload {
+                    from_broadcaster_user_id,
+                    from_broadcaster_user_name,
+                    to_broadcaster_user_id,
+                    to_broadcaster_user_login,
+                    to_broadcaster_user_name,
+                    viewers,
+                    ..
+                }),
+            ..
+        }) => {
+            tokio::spawn(async move {
+                notifier
+                    .channel_raid(
+                        from_broadcaster_user_id.to_string(),
+                        from_broadcaster_user_name.to_string(),
+                        Broadcaster {
+                            id: to_broadcaster_user_id.to_string(),
+                            login: to_broadcaster_user_login.to_string(),
+                            display_name: to_broadcaster_user_name.to_string(),
+                        },
+                        viewers,
+                    )
+                    .await
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Hands a revocation over to the registrations, whichever transport it came
+/// from. `pointer` leads to the subscription within the raw message.
+pub fn dispatch_revocation(
+    message: &[u8],
+    pointer: &str,
+    registrations: Arc<RegistrationSupervisor>,
+    notifier: Arc<Notifier>,
+) -> Result<(), eyre::Report> {
+    // The typed payload differs per event, the raw one has all we need
+    let mut message =
+        serde_json::from_slice::<serde_json::Value>(message).wrap_err("when parsing revocation")?;
+
+    let subscription = message
+        .pointer_mut(pointer)
+        .map(serde_json::Value::take)
+        .unwrap_or_default();
+
+    tokio::spawn(async move { handle_revocation(subscription, registrations, notifier).await });
+
+    Ok(())
+}
+
+async fn handle_revocation(
+    subscription: serde_json::Value,
+    registrations: Arc<RegistrationSupervisor>,
+    notifier: Arc<Notifier>,
+) {
+    let broadcaster_id = match condition_broadcaster_id(&subscription["condition"]) {
+        Some(v) => v.to_string(),
+        None => return,
+    };
+
+    let status = serde_json::from_value::<Status>(subscription["status"].clone()).ok();
+
+    tracing::warn!(
+        "EventSub subscription {} of {} revoked: {:?}",
+        subscription["type"],
+        broadcaster_id,
+        status
+    );
+
+    let subscription_id = subscription["id"].as_str().unwrap_or_default().to_string();
+
+    if let Err(err) = EventSubRepository::delete_by_subscription_id(subscription_id.clone()).await {
+        tracing::error!(
+            "Failed to delete registration {}: {:?}",
+            subscription_id,
+            err
+        );
+    }
+
+    match status {
+        // Our endpoint was unreachable for a while, nothing is wrong with the channel
+        Some(Status::NotificationFailuresExceeded) => {
+            registrations.reregister(broadcaster_id).await;
+        }
+        // The channel was deleted or banned
+        Some(Status::UserRemoved) => {
+            registrations
+                .revoke(&broadcaster_id, serialized_name(&Status::UserRemoved))
+                .await;
+
+            notifier.channel_removed(broadcaster_id).await;
+        }
+        status => {
+            registrations
+                .revoke(
+                    &broadcaster_id,
+                    status.map(|v| serialized_name(&v)).unwrap_or_default(),
+                )
+                .await;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "state", content = "details", rename_all = "snake_case")]
+pub enum RegistrationState {
+    Pending,
+    Registered,
+    Retrying {
+        attempt: u32,
+        #[serde(serialize_with = "serialize_secs")]
+        retry_in: Duration,
+        error: String,
+    },
+    Failed(String),
+    Revoked(String),
+    OverCostLimit,
+}
+
+fn serialize_secs<S: serde::Serializer>(
+    value: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(value.as_secs())
+}
+
+/// Keeps one registration task per broadcaster, so a slow or failing
+/// broadcaster never blocks the others.
+pub struct RegistrationSupervisor {
+    target: RwLock<Option<EventSubTarget>>,
+    states: Arc<RwLock<HashMap<String, RegistrationState>>>,
+    tasks: RwLock<HashMap<String, JoinHandle<()>>>,
+    verified_at: RwLock<HashMap<String, DateTime>>,
+    registering: Arc<RwLock<()>>,
+}
+
+impl RegistrationSupervisor {
+    const INITIAL_DELAY: Duration = Duration::from_secs(5);
+    const REVERIFY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+    const BASE_BACKOFF: Duration = Duration::from_secs(5);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+    const MAX_ATTEMPTS: u32 = 8;
+    const COST_LIMIT_RECHECK: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new(target: Option<EventSubTarget>) -> Self {
+        Self {
+            target: RwLock::new(target),
+            states: Arc::new(RwLock::new(HashMap::new())),
+            tasks: RwLock::new(HashMap::new()),
+            verified_at: RwLock::new(HashMap::new()),
+            registering: Arc::new(RwLock::new(())),
+        }
+    }
+
+    /// Restores what was verified before the restart, so those broadcasters
+    /// are only re-checked when their verification gets stale.
+    pub async fn load(&self) -> mongodb::error::Result<()> {
+        let registrations = EventSubRepository::all().await?;
+
+        let mut by_broadcaster: HashMap<String, (HashSet<String>, DateTime)> = HashMap::new();
+
+        for registration in registrations {
+            let (types, verified_at) = by_broadcaster
+                .entry(registration.broadcaster_id)
+                .or_insert_with(|| (HashSet::new(), registration.verified_at));
+
+            types.insert(registration.type_);
+            *verified_at = (*verified_at).min(registration.verified_at);
+        }
+
+        let event_types = event_types();
+
+        let mut verified_at = self.verified_at.write().await;
+
+        for (broadcaster_id, (types, at)) in by_broadcaster {
+            // Registrations saved before an event type was added miss it
+            if event_types
+                .iter()
+                .all(|event_type| types.contains(event_type))
+            {
+                verified_at.insert(broadcaster_id, at);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Points registrations at a new transport, e.g. a fresh WebSocket
+    /// session. Subscriptions don't carry over, so everything starts again.
+    pub async fn set_target(&self, target: EventSubTarget) {
+        {
+            let mut current = self.target.write().await;
+
+            if current
+                .as_ref()
+                .is_some_and(|current| current.same_transport(&target))
+            {
+                return;
+            }
+
+            *current = Some(target);
+        }
+
+        for (_, task) in self.tasks.write().await.drain() {
+            task.abort();
+        }
+
+        self.states.write().await.clear();
+        self.verified_at.write().await.clear();
+
+        // The rows describe subscriptions of the previous transport
+        if let Err(err) = EventSubRepository::delete_all().await {
+            tracing::error!("Failed to delete stale registrations: {:?}", err);
+        }
+    }
+
+    /// Takes the transport away, e.g. when the WebSocket session is gone.
+    /// Everything stays pending, so polling covers it until `set_target`.
+    pub async fn suspend(&self) {
+        *self.target.write().await = None;
+
+        for (_, task) in self.tasks.write().await.drain() {
+            task.abort();
+        }
+
+        for state in self.states.write().await.values_mut() {
+            *state = RegistrationState::Pending;
+        }
+    }
+
+    pub async fn states(&self) -> HashMap<String, RegistrationState> {
+        self.states.read().await.clone()
+    }
+
+    /// Broadcasters whose events Twitch is known to deliver to us.
+    pub async fn registered(&self) -> HashSet<String> {
+        self.states
+            .read()
+            .await
+            .iter()
+            .filter(|(_, state)| matches!(state, RegistrationState::Registered))
+            .map(|(broadcaster_id, _)| broadcaster_id.clone())
+            .collect()
+    }
+
+    pub async fn supervised(&self) -> Vec<String> {
+        self.tasks.read().await.keys().cloned().collect()
+    }
+
+    pub async fn ensure(&self, broadcaster_id: String) {
+        let target = match self.target.read().await.clone() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let mut tasks = self.tasks.write().await;
+
+        if tasks.contains_key(&broadcaster_id) {
+            return;
+        }
+
+        let verified_ago = self.verified_at.read().await.get(&broadcaster_id).map(|v| {
+            Duration::from_millis(
+                (DateTime::now().timestamp_millis() - v.timestamp_millis()).max(0) as u64,
+            )
+        });
+
+        let (state, first_check_in) = match verified_ago {
+            Some(ago) if ago < Self::REVERIFY_INTERVAL => {
+                (RegistrationState::Registered, Self::REVERIFY_INTERVAL - ago)
+            }
+            _ => (RegistrationState::Pending, Self::INITIAL_DELAY),
+        };
+
+        Self::set_state(&self.states, &broadcaster_id, state).await;
+
+        let task = tokio::spawn(Self::run(
+            target,
+            self.states.clone(),
+            self.registering.clone(),
+            broadcaster_id.clone(),
+            first_check_in,
+        ));
+
+        tasks.insert(broadcaster_id, task);
+    }
+
+    /// Starts registering a broadcaster from scratch, e.g. after Twitch
+    /// revoked one of its subscriptions for a recoverable reason.
+    pub async fn reregister(&self, broadcaster_id: String) {
+        if let Some(task) = self.tasks.write().await.remove(&broadcaster_id) {
+            task.abort();
+        }
+
+        self.verified_at.write().await.remove(&broadcaster_id);
+
+        self.ensure(broadcaster_id).await;
+    }
+
+    /// Stops registering a broadcaster Twitch won't deliver events for. The
+    /// finished task stays in place, so `ensure` doesn't bring it back.
+    pub async fn revoke(&self, broadcaster_id: &str, reason: String) {
+        if let Some(task) = self.tasks.read().await.get(broadcaster_id) {
+            task.abort();
+        }
+
+        Self::set_state(
+            &self.states,
+            broadcaster_id,
+            RegistrationState::Revoked(reason),
+        )
+        .await;
+    }
+
+    /// Stops supervising a broadcaster nobody follows anymore and deletes
+    /// its subscriptions on Twitch.
+    pub async fn release(&self, broadcaster_id: &str) {
+        let task = match self.tasks.write().await.remove(broadcaster_id) {
+            Some(v) => v,
+            None => return,
+        };
+
+        task.abort();
+
+        self.states.write().await.remove(broadcaster_id);
+        self.verified_at.write().await.remove(broadcaster_id);
+
+        tracing::info!("Releasing EventSub registrations for {}", broadcaster_id);
+
+        let target = match self.target.read().await.clone() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let registrations = match EventSubRepository::all_by_broadcaster(broadcaster_id).await {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to load registrations of {}: {:?}",
+                    broadcaster_id,
+                    err
+                );
+                return;
+            }
+        };
+
+        for registration in registrations {
+            if let Err(err) = eventsub_unregister(&target, registration.subscription_id).await {
+                tracing::error!("Failed to unregister {}: {:?}", broadcaster_id, err);
+            }
+        }
+    }
+
+    /// Deletes subscriptions pointing at our callback whose broadcaster
+    /// nobody follows, e.g. ones left over from a crash before `release`.
+    pub async fn sweep(&self, followed: &HashSet<String>) -> Result<(), eyre::Report> {
+        let _guard = self.registering.write().await;
+
+        let target = match self.target.read().await.clone() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let client: HelixClient<'_, reqwest::Client> = HelixClient::new();
+
+        let (subs, _) = match &target {
+            EventSubTarget::Webhook { twitch_client, .. } => {
+                twitch_client
+                    .with_app_access_token(|| async {
+                        eventsub_subscriptions(
+                            &client,
+                            &*twitch_client.app_access_token.read().await,
+                            None,
+                            &target,
+                        )
+                        .await
+                    })
+                    .await?
+            }
+            EventSubTarget::WebSocket { twitch_client, .. } => {
+                twitch_client
+                    .with_user_access_token(|| async {
+                        eventsub_subscriptions(
+                            &client,
+                            &*twitch_client.user_token()?.read().await,
+                            None,
+                            &target,
+                        )
+                        .await
+                    })
+                    .await?
+            }
+        };
+
+        for sub in subs {
+            let is_followed = condition_broadcaster_id(&sub.condition)
+                .is_some_and(|broadcaster_id| followed.contains(broadcaster_id));
+
+            if is_followed {
+                continue;
+            }
+
+            tracing::info!(
+                "Sweeping orphaned EventSub subscription {:?}",
+                sub.condition
+            );
+
+            if let Err(err) = eventsub_unregister(&target, sub.id.to_string()).await {
+                tracing::error!("Failed to sweep {}: {:?}", sub.id, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps registrations in line with what is followed: new broadcasters
+    /// get registered, abandoned ones released.
+    pub async fn supervise(&self, subscription_manager: &SubscriptionManager) {
+        loop {
+            let broadcaster_ids = subscription_manager
+                .subscriptions
+                .read()
+                .await
+                .keys()
+                .cloned()
+                .collect::<HashSet<String>>();
+
+            for broadcaster_id in broadcaster_ids.iter() {
+                self.ensure(broadcaster_id.clone()).await;
+            }
+
+            for broadcaster_id in self.supervised().await {
+                if !broadcaster_ids.contains(&broadcaster_id) {
+                    self.release(&broadcaster_id).await;
                 }
             }
+
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         }
-        _ => {}
     }
-    (StatusCode::OK, String::default())
+
+    async fn set_state(
+        states: &RwLock<HashMap<String, RegistrationState>>,
+        broadcaster_id: &str,
+        state: RegistrationState,
+    ) {
+        tracing::info!("EventSub registration for {}: {:?}", broadcaster_id, state);
+
+        states
+            .write()
+            .await
+            .insert(broadcaster_id.to_string(), state);
+    }
+
+    fn backoff(attempt: u32) -> Duration {
+        Self::BASE_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(Self::MAX_BACKOFF)
+    }
+
+    async fn run(
+        target: EventSubTarget,
+        states: Arc<RwLock<HashMap<String, RegistrationState>>>,
+        registering: Arc<RwLock<()>>,
+        broadcaster_id: String,
+        first_check_in: Duration,
+    ) {
+        tokio::time::sleep(first_check_in).await;
+
+        let mut attempt = 0;
+
+        loop {
+            let result = {
+                let _guard = registering.read().await;
+
+                eventsub_register(target.clone(), broadcaster_id.clone()).await
+            };
+
+            let (state, sleep_for) = match result {
+                Ok(_) => {
+                    attempt = 0;
+                    (RegistrationState::Registered, Self::REVERIFY_INTERVAL)
+                }
+                // Not worth backing off for, it only clears up once others unsubscribe
+                Err(err) if err.downcast_ref::<CostLimitExceeded>().is_some() => {
+                    attempt = 0;
+                    (RegistrationState::OverCostLimit, Self::COST_LIMIT_RECHECK)
+                }
+                Err(err) => {
+                    attempt += 1;
+
+                    if attempt >= Self::MAX_ATTEMPTS {
+                        attempt = 0;
+                        (
+                            RegistrationState::Failed(format!("{:?}", err)),
+                            Self::REVERIFY_INTERVAL,
+                        )
+                    } else {
+                        let retry_in = Self::backoff(attempt);
+                        (
+                            RegistrationState::Retrying {
+                                attempt,
+                                retry_in,
+                                error: format!("{:?}", err),
+                            },
+                            retry_in,
+                        )
+                    }
+                }
+            };
+
+            Self::set_state(&states, &broadcaster_id, state).await;
+
+            tokio::time::sleep(sleep_for).await;
+        }
+    }
+}
+
+/// Token health as served on `/twitch/health`. The app token fields stay at
+/// the top level, the user token is only there for the WebSocket transport.
+#[derive(Serialize)]
+struct TwitchHealth {
+    #[serde(flatten)]
+    app_token: TokenHealth,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_token: Option<TokenHealth>,
+    /// EventSub registration state by broadcaster id, none when polling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registrations: Option<HashMap<String, RegistrationState>>,
+}
+
+pub async fn twitch_health(
+    Extension(twitch_client): Extension<Arc<TwitchClient>>,
+    Extension(registrations): Extension<Option<Arc<RegistrationSupervisor>>>,
+) -> impl IntoResponse {
+    let health = TwitchHealth {
+        app_token: twitch_client.token_health().await,
+        user_token: twitch_client.user_token_health().await,
+        registrations: match registrations {
+            Some(registrations) => Some(registrations.states().await),
+            None => None,
+        },
+    };
+
+    let healthy =
+        health.app_token.healthy && health.user_token.as_ref().is_none_or(|token| token.healthy);
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(health))
+}
+
+/// Serves `/twitch/health` for the transports that don't run the webhook
+/// server, on `TWITCH_WEBHOOK_PORT` if it is set.
+async fn start_health_server(
+    twitch_client: Arc<TwitchClient>,
+    registrations: Option<Arc<RegistrationSupervisor>>,
+) {
+    if CONFIG.twitch_webhook_port == 0 {
+        tracing::warn!("TWITCH_WEBHOOK_PORT is not set, /twitch/health is not served");
+        return;
+    }
+
+    let app = Router::new()
+        .route("/twitch/health", get(twitch_health))
+        .layer(Extension(twitch_client))
+        .layer(Extension(registrations))
+        .layer(TraceLayer::new_for_http());
+
+    let address = SocketAddr::new([0, 0, 0, 0].into(), CONFIG.twitch_webhook_port);
+
+    let _ = axum::serve(
+        TcpListener::bind(address).await.unwrap(),
+        app.into_make_service(),
+    )
+    .await;
 }
 
 struct TwitchWebhookServer {
     subscription_manager: Arc<SubscriptionManager>,
-    subscribed_to: Arc<RwLock<Vec<String>>>,
-    app_access_token: Arc<RwLock<AppAccessToken>>,
+    twitch_client: Arc<TwitchClient>,
+    notifier: Arc<Notifier>,
+    registrations: Arc<RegistrationSupervisor>,
 }
 
 impl TwitchWebhookServer {
     pub fn new(
         subscription_manager: Arc<SubscriptionManager>,
-        app_access_token: Arc<RwLock<AppAccessToken>>,
+        twitch_client: Arc<TwitchClient>,
+        notifier: Arc<Notifier>,
     ) -> Self {
         Self {
             subscription_manager,
-            subscribed_to: Arc::new(RwLock::new(Vec::new())),
-            app_access_token,
+            twitch_client: twitch_client.clone(),
+            notifier,
+            registrations: Arc::new(RegistrationSupervisor::new(Some(EventSubTarget::Webhook {
+                twitch_client,
+                callback: format!("{}/twitch/eventsub/", CONFIG.twitch_webhook_url),
+            }))),
         }
     }
 
+    pub fn registrations(&self) -> Arc<RegistrationSupervisor> {
+        self.registrations.clone()
+    }
+
     pub async fn start_webhook_server(&self) {
         let retainer = Arc::new(retainer::Cache::<axum::http::HeaderValue, ()>::new());
 
@@ -201,10 +1157,16 @@ impl TwitchWebhookServer {
         let app = Router::new()
             .route(
                 "/twitch/eventsub/",
-                post(move |cache, subs, request| twitch_eventsub(cache, subs, request)),
+                post(move |cache, notifier, registrations, request| {
+                    twitch_eventsub(cache, notifier, registrations, request)
+                }),
             )
+            .route("/twitch/health", get(twitch_health))
             .layer(Extension(retainer))
-            .layer(Extension(self.subscription_manager.clone()))
+            .layer(Extension(self.twitch_client.clone()))
+            .layer(Extension(self.notifier.clone()))
+            .layer(Extension(self.registrations.clone()))
+            .layer(Extension(Some(self.registrations.clone())))
             .layer(TraceLayer::new_for_http());
 
         let address = SocketAddr::new([0, 0, 0, 0].into(), CONFIG.twitch_webhook_port);
@@ -216,54 +1178,38 @@ impl TwitchWebhookServer {
         .await;
     }
 
-    pub async fn subscribe(&self, streamer: String) -> bool {
-        tracing::info!("Subscribing to {}", streamer);
-
-        match eventsub_register(
-            self.app_access_token.clone(),
-            streamer.clone(),
-            format!("{}/twitch/eventsub/", CONFIG.twitch_webhook_url),
-        )
-        .await
-        {
-            Ok(_) => true,
-            Err(err) => {
-                tracing::error!("Failed to subscribe to {}: {:?}", streamer, err);
-                false
-            }
-        }
-    }
+    pub async fn sweep_subscriptions(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
 
-    pub async fn check_subscriptions(&self) {
         loop {
-            let streamers = self
+            interval.tick().await;
+
+            let followed = self
                 .subscription_manager
                 .subscriptions
                 .read()
                 .await
                 .keys()
                 .cloned()
-                .collect::<Vec<String>>();
-
-            for streamer in streamers {
-                let is_subscribed = self.subscribed_to.read().await.contains(&streamer);
+                .collect::<HashSet<String>>();
 
-                if !is_subscribed {
-                    if self.subscribe(streamer.clone()).await {
-                        self.subscribed_to.write().await.push(streamer);
-                    }
-                }
+            if let Err(err) = self.registrations.sweep(&followed).await {
+                tracing::error!("Failed to sweep EventSub subscriptions: {:?}", err);
             }
-
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
         }
     }
 
     pub async fn start(&self) -> Result<(), eyre::Report> {
-        let subscribe_future = self.check_subscriptions();
+        self.registrations
+            .load()
+            .await
+            .wrap_err("when loading EventSub registrations")?;
+
+        let subscribe_future = self.registrations.supervise(&self.subscription_manager);
+        let sweep_future = self.sweep_subscriptions();
         let webhook_future = self.start_webhook_server();
 
-        futures::join!(subscribe_future, webhook_future);
+        futures::join!(subscribe_future, sweep_future, webhook_future);
 
         Ok(())
     }
@@ -271,29 +1217,78 @@ impl TwitchWebhookServer {
 
 pub async fn start_twitch_webhook(
     subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
 ) -> Result<(), eyre::Report> {
-    let client: HelixClient<_> = twitch_api::HelixClient::with_client(
-        <reqwest::Client>::default_client_with_name(Some(
-            "twitch-rs/eventsub"
-                .parse()
-                .wrap_err_with(|| "when creating header name")
-                .unwrap(),
-        ))
-        .wrap_err_with(|| "when creating client")?,
+    let notifier = Arc::new(Notifier::new(
+        subscription_manager.clone(),
+        twitch_client.clone(),
+    ));
+
+    notifier.load_channel_states().await;
+
+    let (result, _) = futures::join!(
+        start_transport(subscription_manager, twitch_client, notifier.clone()),
+        notifier.deliver_digests()
     );
 
-    let token = twitch_oauth2::AppAccessToken::get_app_access_token(
-        &client,
-        CONFIG.twitch_client_id.clone().into(),
-        CONFIG.twitch_client_secret.clone().into(),
-        vec![],
-    )
-    .await?;
+    result
+}
+
+async fn start_transport(
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+    notifier: Arc<Notifier>,
+) -> Result<(), eyre::Report> {
+    match CONFIG.twitch_eventsub_transport {
+        EventSubTransport::Webhook => {
+            let twitch_webhook_server = TwitchWebhookServer::new(
+                subscription_manager.clone(),
+                twitch_client.clone(),
+                notifier.clone(),
+            );
+            let twitch_poller = TwitchPoller::new(
+                subscription_manager,
+                twitch_client,
+                notifier,
+                Some(twitch_webhook_server.registrations()),
+            );
 
-    let token = Arc::new(tokio::sync::RwLock::new(token));
+            let (result, _) = futures::join!(twitch_webhook_server.start(), twitch_poller.start());
+            result?;
+        }
+        EventSubTransport::WebSocket => {
+            if twitch_client.user_access_token.is_none() {
+                eyre::bail!("a user access token is required for the WebSocket transport");
+            }
 
-    let twitch_webhook_server = TwitchWebhookServer::new(subscription_manager, token);
-    let _ = twitch_webhook_server.start().await;
+            let twitch_websocket_client = TwitchWebSocketClient::new(
+                subscription_manager.clone(),
+                twitch_client.clone(),
+                notifier.clone(),
+            );
+            let twitch_poller = TwitchPoller::new(
+                subscription_manager,
+                twitch_client.clone(),
+                notifier,
+                Some(twitch_websocket_client.registrations()),
+            );
+
+            futures::join!(
+                twitch_websocket_client.start(),
+                twitch_poller.start(),
+                start_health_server(twitch_client, Some(twitch_websocket_client.registrations()))
+            );
+        }
+        EventSubTransport::Polling => {
+            let twitch_poller =
+                TwitchPoller::new(subscription_manager, twitch_client.clone(), notifier, None);
+
+            futures::join!(
+                twitch_poller.start(),
+                start_health_server(twitch_client, None)
+            );
+        }
+    }
 
     Ok(())
 }
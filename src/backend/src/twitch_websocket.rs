@@ -0,0 +1,244 @@
+use std::{sync::Arc, time::Duration};
+
+use eyre::Context;
+use futures::{FutureExt as _, StreamExt as _, future::BoxFuture};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message as WsMessage};
+use twitch_api::eventsub::{
+    Event, EventsubWebsocketData, ReconnectPayload, SessionData, WelcomePayload,
+};
+
+use crate::{
+    config::CONFIG,
+    notifier::Notifier,
+    subscription_manager::SubscriptionManager,
+    twitch_client::TwitchClient,
+    twitch_webhook::{EventSubTarget, RegistrationSupervisor, dispatch_event, dispatch_revocation},
+};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A connection Twitch asked us to move to, along with its welcome message.
+struct Reconnect {
+    socket: WebSocket,
+    welcome: WsMessage,
+}
+
+/// Receives EventSub notifications over a WebSocket, for deployments that
+/// can't expose a public webhook.
+pub struct TwitchWebSocketClient {
+    subscription_manager: Arc<SubscriptionManager>,
+    twitch_client: Arc<TwitchClient>,
+    notifier: Arc<Notifier>,
+    registrations: Arc<RegistrationSupervisor>,
+}
+
+impl TwitchWebSocketClient {
+    const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+    const DEFAULT_KEEPALIVE: Duration = Duration::from_secs(10);
+    const KEEPALIVE_SLACK: Duration = Duration::from_secs(5);
+
+    pub fn new(
+        subscription_manager: Arc<SubscriptionManager>,
+        twitch_client: Arc<TwitchClient>,
+        notifier: Arc<Notifier>,
+    ) -> Self {
+        Self {
+            subscription_manager,
+            twitch_client,
+            notifier,
+            // Nothing can be registered until the welcome message names a session
+            registrations: Arc::new(RegistrationSupervisor::new(None)),
+        }
+    }
+
+    pub fn registrations(&self) -> Arc<RegistrationSupervisor> {
+        self.registrations.clone()
+    }
+
+    pub async fn start(&self) {
+        let subscribe_future = self.registrations.supervise(&self.subscription_manager);
+        let listen_future = self.listen();
+
+        futures::join!(subscribe_future, listen_future);
+    }
+
+    async fn listen(&self) {
+        let mut reconnect = None;
+
+        loop {
+            let result = match reconnect.take() {
+                Some(Reconnect { socket, welcome }) => self.read(socket, Some(welcome)).await,
+                None => {
+                    match tokio_tungstenite::connect_async(
+                        CONFIG.twitch_eventsub_websocket_url.as_str(),
+                    )
+                    .await
+                    .wrap_err("when connecting")
+                    {
+                        Ok((socket, _)) => self.read(socket, None).await,
+                        Err(err) => Err(err),
+                    }
+                }
+            };
+
+            match result {
+                Ok(Some(v)) => {
+                    tracing::info!("EventSub WebSocket moved to a new connection");
+
+                    reconnect = Some(v);
+                    continue;
+                }
+                Ok(None) => tracing::warn!("EventSub WebSocket closed"),
+                Err(err) => tracing::error!("EventSub WebSocket failed: {:?}", err),
+            }
+
+            // The subscriptions went with the session
+            self.registrations.suspend().await;
+
+            tokio::time::sleep(Self::RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Connects to the url Twitch asked us to move to and waits for its
+    /// welcome message.
+    async fn reconnect(url: String) -> Result<Reconnect, eyre::Report> {
+        tracing::info!("EventSub WebSocket reconnecting to {}", url);
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .wrap_err("when reconnecting")?;
+
+        loop {
+            match tokio::time::timeout(
+                Self::DEFAULT_KEEPALIVE + Self::KEEPALIVE_SLACK,
+                socket.next(),
+            )
+            .await
+            {
+                Ok(Some(message)) => {
+                    let message = message.wrap_err("when reading welcome")?;
+
+                    if let WsMessage::Text(_) = message {
+                        return Ok(Reconnect {
+                            socket,
+                            welcome: message,
+                        });
+                    }
+                }
+                Ok(None) => eyre::bail!("closed before the welcome message"),
+                Err(_) => eyre::bail!("no welcome message"),
+            }
+        }
+    }
+
+    /// Reads messages until the connection is over. Returns the connection
+    /// Twitch asked us to move to, if that is why it is over.
+    async fn read(
+        &self,
+        mut socket: WebSocket,
+        welcome: Option<WsMessage>,
+    ) -> Result<Option<Reconnect>, eyre::Report> {
+        let mut keepalive = Self::DEFAULT_KEEPALIVE;
+
+        let mut pending = welcome;
+        // The old connection keeps delivering events until the new one is welcomed
+        let mut reconnecting: Option<BoxFuture<'static, Result<Reconnect, eyre::Report>>> = None;
+
+        loop {
+            let message = match pending.take() {
+                Some(v) => v,
+                None => {
+                    let reconnected = async {
+                        match reconnecting.as_mut() {
+                            Some(reconnect) => reconnect.await,
+                            None => std::future::pending().await,
+                        }
+                    };
+
+                    tokio::select! {
+                        result = reconnected => match result {
+                            Ok(reconnect) => return Ok(Some(reconnect)),
+                            Err(err) => {
+                                tracing::error!("EventSub WebSocket failed to reconnect: {:?}", err);
+
+                                reconnecting = None;
+                                continue;
+                            }
+                        },
+                        message = tokio::time::timeout(
+                            keepalive + Self::KEEPALIVE_SLACK,
+                            socket.next(),
+                        ) => match message {
+                            Ok(Some(message)) => message.wrap_err("when reading message")?,
+                            Ok(None) => return Ok(None),
+                            Err(_) => eyre::bail!("no message within {:?}", keepalive),
+                        },
+                    }
+                }
+            };
+
+            let text = match message {
+                WsMessage::Text(text) => text,
+                WsMessage::Close(frame) => {
+                    tracing::warn!("EventSub WebSocket close frame: {:?}", frame);
+                    return Ok(None);
+                }
+                _ => continue,
+            };
+
+            match Event::parse_websocket(text.as_str()).wrap_err("when parsing message")? {
+                EventsubWebsocketData::Welcome {
+                    payload:
+                        WelcomePayload {
+                            session:
+                                SessionData {
+                                    id,
+                                    keepalive_timeout_seconds,
+                                    ..
+                                },
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(seconds) = keepalive_timeout_seconds {
+                        keepalive = Duration::from_secs(seconds.max(1) as u64);
+                    }
+
+                    tracing::info!("EventSub WebSocket session {}", id);
+
+                    self.registrations
+                        .set_target(EventSubTarget::WebSocket {
+                            twitch_client: self.twitch_client.clone(),
+                            session_id: id.to_string(),
+                        })
+                        .await;
+                }
+                EventsubWebsocketData::Notification { payload, .. } => {
+                    dispatch_event(payload, self.notifier.clone());
+                }
+                EventsubWebsocketData::Revocation { .. } => {
+                    dispatch_revocation(
+                        text.as_str().as_bytes(),
+                        "/payload/subscription",
+                        self.registrations.clone(),
+                        self.notifier.clone(),
+                    )?;
+                }
+                EventsubWebsocketData::Reconnect {
+                    payload:
+                        ReconnectPayload {
+                            session: SessionData { reconnect_url, .. },
+                            ..
+                        },
+                    ..
+                } => {
+                    if let Some(reconnect_url) = reconnect_url {
+                        reconnecting = Some(Self::reconnect(reconnect_url.to_string()).boxed());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -2,24 +2,24 @@ pub mod auth;
 pub mod subscriptions;
 pub mod validation;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::Router;
 use subscriptions::get_api_router;
 use tokio::net::TcpListener;
 use tower_http::services::ServeFile;
 
-use crate::config::CONFIG;
+use crate::{config::CONFIG, twitch_client::TwitchClient};
 
-fn get_app() -> Router {
+fn get_app(twitch_client: Arc<TwitchClient>) -> Router {
     Router::new()
         .nest_service("/assets", ServeFile::new("assets"))
-        .nest("/api", get_api_router())
+        .nest("/api", get_api_router(twitch_client))
         .fallback_service(ServeFile::new("assets/index.html"))
 }
 
-pub async fn start_web_app() -> Result<(), eyre::Report> {
-    let app = get_app();
+pub async fn start_web_app(twitch_client: Arc<TwitchClient>) -> Result<(), eyre::Report> {
+    let app = get_app(twitch_client);
 
     let address = SocketAddr::new([0, 0, 0, 0].into(), CONFIG.telegram_mini_app_port);
 
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use axum::{
     Extension, Json, Router,
     extract::Path,
@@ -6,12 +8,18 @@ use axum::{
     routing::{delete, get, post},
 };
 
-use crate::repositories::subscriptions::SubscriptionRepository;
+use crate::{
+    repositories::subscriptions::{SubscriptionRepository, SubscriptionSource},
+    twitch_client::TwitchClient,
+};
 
 use super::auth::{AuthLayer, UserId};
 
 async fn get_subscriptions(Extension(UserId(user_id)): Extension<UserId>) -> impl IntoResponse {
-    let subs = SubscriptionRepository::all_by_user(user_id).await.unwrap();
+    // The mini app manages the user's private chat, which shares its id
+    let subs = SubscriptionRepository::all_by_chat(user_id as i64)
+        .await
+        .unwrap();
 
     Json(subs).into_response()
 }
@@ -19,29 +27,45 @@ async fn get_subscriptions(Extension(UserId(user_id)): Extension<UserId>) -> imp
 async fn create_subscription(
     Path(streamer): Path<String>,
     Extension(UserId(user_id)): Extension<UserId>,
+    Extension(twitch_client): Extension<Arc<TwitchClient>>,
 ) -> impl IntoResponse {
-    let sub = SubscriptionRepository::get_or_create(streamer, user_id)
+    let broadcaster = match twitch_client
+        .get_broadcaster_by_login(&streamer.to_lowercase())
         .await
-        .unwrap();
+        .unwrap()
+    {
+        Some(v) => v,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let sub = SubscriptionRepository::get_or_create(
+        broadcaster,
+        user_id as i64,
+        None,
+        SubscriptionSource::WebApp,
+    )
+    .await
+    .unwrap();
 
     Json(sub).into_response()
 }
 
 async fn delete_subscription(
-    Path(streamer): Path<String>,
+    Path(broadcaster_id): Path<String>,
     Extension(UserId(user_id)): Extension<UserId>,
 ) -> impl IntoResponse {
-    SubscriptionRepository::delete(streamer, user_id)
+    SubscriptionRepository::delete(broadcaster_id, user_id as i64)
         .await
         .unwrap();
 
     StatusCode::NO_CONTENT
 }
 
-pub fn get_api_router() -> Router {
+pub fn get_api_router(twitch_client: Arc<TwitchClient>) -> Router {
     Router::new()
         .route("/subscriptions/", get(get_subscriptions))
         .route("/subscriptions/:streamer/", post(create_subscription))
         .route("/subscriptions/:streamer/", delete(delete_subscription))
+        .layer(Extension(twitch_client))
         .layer(AuthLayer)
 }